@@ -0,0 +1,138 @@
+//! Compares the parser `Query` used to have, which allocates for every key
+//! and value, with parsing into a `Query` and into a borrowed `QueryRef`.
+//! Run with `cargo bench --bench query`.
+
+use std::{
+	hint::black_box,
+	time::{Duration, Instant},
+};
+
+use mavourings::query::{Query, QueryRef};
+
+const ITERATIONS: u32 = 200_000;
+
+const QUERIES: [(&str, &str); 3] = [
+	("plain", "page=2&sort=new&tag=rust&tag=web&preview"),
+	(
+		"encoded",
+		"q=cat+pics%21&next=%2Fposts%3Fpage%3D2&name=g%C3%A9nny&tag=a%26b",
+	),
+	(
+		"long",
+		"utm_source=newsletter&utm_medium=email&utm_campaign=spring&utm_content=header&ref=home&session=8f14e45fceea167a5a36dedd4bea2543&lang=en&theme=dark",
+	),
+];
+
+/// The char-based parser from before `QueryRef`, kept here so there's
+/// something to compare against. It builds tuples because `Query`'s fields
+/// are private, but allocates the same.
+mod old {
+	/// A key and value, or a bool's name and no value
+	type Parameter = (String, Option<String>);
+
+	pub fn parse(s: &str) -> Result<(Vec<Parameter>, Vec<String>), ()> {
+		let mut parameters: Vec<Parameter> = vec![];
+		let mut raw_keys: Vec<String> = vec![];
+
+		for split in s.split('&').filter(|split| !split.is_empty()) {
+			let (raw_key, parameter) = match split.split_once('=') {
+				None => (split, (url_decode(split, true)?, None)),
+				Some((key, value)) => (
+					key,
+					(url_decode(key, true)?, Some(url_decode(value, true)?)),
+				),
+			};
+
+			parameters.push(parameter);
+			raw_keys.push(raw_key.to_owned());
+		}
+
+		Ok((parameters, raw_keys))
+	}
+
+	fn url_decode(urlencoded: &str, plus_as_space: bool) -> Result<String, ()> {
+		let mut uncoded: Vec<u8> = vec![];
+
+		let mut chars = urlencoded.chars().peekable();
+		loop {
+			match chars.next() {
+				Some('+') => match plus_as_space {
+					true => uncoded.push(b' '),
+					false => uncoded.push(b'+'),
+				},
+				Some('%') => match chars.peek() {
+					Some(c) if c.is_ascii_hexdigit() => {
+						let upper = chars.next().unwrap();
+
+						if let Some(lower) = chars.peek() {
+							if lower.is_ascii_hexdigit() {
+								let upper = upper.to_digit(16).unwrap();
+								let lower = chars.next().unwrap().to_digit(16).unwrap();
+
+								uncoded.push(upper as u8 * 16 + lower as u8);
+								continue;
+							}
+						}
+
+						uncoded.push(b'%');
+						uncoded.extend_from_slice(&char_bytes(upper));
+					}
+					_ => {
+						uncoded.push(b'%');
+					}
+				},
+				Some(c) => {
+					uncoded.extend_from_slice(&char_bytes(c));
+				}
+				None => return String::from_utf8(uncoded).map_err(|_| ()),
+			}
+		}
+	}
+
+	fn char_bytes(c: char) -> Vec<u8> {
+		let mut utf8 = vec![0; c.len_utf8()];
+		c.encode_utf8(&mut utf8);
+		utf8
+	}
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) -> Duration {
+	// Warm the caches up first
+	for _ in 0..ITERATIONS / 100 {
+		f();
+	}
+
+	let start = Instant::now();
+	for _ in 0..ITERATIONS {
+		f();
+	}
+	let per_iteration = start.elapsed() / ITERATIONS;
+
+	println!("{name:<20} {per_iteration:>10?}");
+	per_iteration
+}
+
+fn main() {
+	for (label, query) in QUERIES {
+		let old = bench(&format!("old/{label}"), || {
+			black_box(old::parse(black_box(query)).unwrap());
+		});
+
+		let owned = bench(&format!("Query/{label}"), || {
+			black_box(black_box(query).parse::<Query>().unwrap());
+		});
+
+		let borrowed = bench(&format!("QueryRef/{label}"), || {
+			black_box(QueryRef::parse(black_box(query)).unwrap());
+		});
+
+		for (name, new) in [("Query", owned), ("QueryRef", borrowed)] {
+			println!(
+				"{:<20} {:>9.2}x",
+				format!("{name} speedup"),
+				old.as_secs_f64() / new.as_secs_f64()
+			);
+		}
+		println!();
+	}
+}
@@ -0,0 +1,656 @@
+use std::{
+	future::Future,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use axum::{
+	extract::{FromRequestParts, OriginalUri, State},
+	http::{header, HeaderMap, Request, StatusCode},
+	response::{AppendHeaders, IntoResponse, Redirect, Response},
+	routing::{get, post},
+	Router,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+	extractors::{FormBody, FormRejection},
+	query::{EncodeSet, Query},
+	users::{self, SessionId, Users},
+};
+
+/// A [Layer] that only lets requests through if they carry a valid `sid`
+/// cookie for one of the [Users].
+///
+/// Browsers, requests that `Accept` text/html, are redirected to the login
+/// path with the page they wanted in the `next` parameter. Everything else
+/// gets a bare 401.
+///
+/// When the session is valid, its [SessionId] and [UserStub](crate::users::UserStub)
+/// are added to the request extensions so handlers can use `Extension` to
+/// get at them.
+#[derive(Clone)]
+pub struct RequireLoginLayer {
+	users: Arc<Users>,
+	login_path: String,
+}
+
+impl RequireLoginLayer {
+	pub fn new(users: Arc<Users>) -> Self {
+		Self {
+			users,
+			login_path: String::from("/login"),
+		}
+	}
+
+	/// Where to send browsers that aren't logged in. Defaults to `/login`
+	pub fn login_path<S: Into<String>>(mut self, path: S) -> Self {
+		self.login_path = path.into();
+		self
+	}
+}
+
+impl<S> Layer<S> for RequireLoginLayer {
+	type Service = RequireLogin<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		RequireLogin {
+			inner,
+			users: self.users.clone(),
+			login_path: self.login_path.clone(),
+		}
+	}
+}
+
+/// The [Service] made by [RequireLoginLayer]
+#[derive(Clone)]
+pub struct RequireLogin<S> {
+	inner: S,
+	users: Arc<Users>,
+	login_path: String,
+}
+
+impl<S, B> Service<Request<B>> for RequireLogin<S>
+where
+	S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+	S::Future: Send + 'static,
+	B: Send + 'static,
+{
+	type Response = Response;
+	type Error = S::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, request: Request<B>) -> Self::Future {
+		// The clone might not be ready, so keep the service we polled
+		let clone = self.inner.clone();
+		let mut inner = std::mem::replace(&mut self.inner, clone);
+
+		let users = self.users.clone();
+		let login_path = self.login_path.clone();
+
+		Box::pin(async move {
+			let (mut parts, body) = request.into_parts();
+
+			let session = match SessionId::from_request_parts(&mut parts, &()).await {
+				Ok(sid) => users.session_by_id(sid).await,
+				Err(()) => None,
+			};
+
+			match session {
+				Some(session) => {
+					parts.extensions.insert(session.sid);
+					parts.extensions.insert(session.stub);
+
+					inner.call(Request::from_parts(parts, body)).await
+				}
+				None if wants_html(&parts.headers) => {
+					let next = parts
+						.uri
+						.path_and_query()
+						.map(|pq| pq.as_str())
+						.unwrap_or("/");
+
+					Ok(login_redirect(&login_path, next).into_response())
+				}
+				None => Ok(StatusCode::UNAUTHORIZED.into_response()),
+			}
+		})
+	}
+}
+
+/// Redirect to the login page, asking it to send the user to `next` afterwards
+pub fn login_redirect(login_path: &str, next: &str) -> Redirect {
+	let mut query = Query::new();
+	query.push_value("next", next);
+
+	Redirect::to(&format!("{login_path}?{query}"))
+}
+
+fn wants_html(headers: &HeaderMap) -> bool {
+	headers
+		.get_all(header::ACCEPT)
+		.iter()
+		.filter_map(|accept| accept.to_str().ok())
+		.any(|accept| accept.contains("text/html"))
+}
+
+/// Builds a [Router] with the login, logout, and register endpoints every app
+/// needs. They take `application/x-www-form-urlencoded` posts with the fields
+/// `username`, `password`, and, for registering, an optional `email`.
+///
+/// All three redirect to the `next` field, or `next` from the query string,
+/// afterwards. Only paths on this site are followed; anything else sends the
+/// user to `/`.
+///
+/// With the `template` feature you can give pages for login and register. They
+/// are served on GET and re-rendered when a post fails, with the variables
+/// `next` and `error` set. Without a page, failures are plain text.
+///
+/// ```rust,no_run
+/// # use std::sync::Arc;
+/// use axum::Router;
+/// use mavourings::{auth::AuthRoutes, users::Users};
+///
+/// let users = Arc::new(Users::new());
+/// let app: Router = Router::new().merge(AuthRoutes::new(users).into_router());
+/// ```
+pub struct AuthRoutes {
+	users: Arc<Users>,
+	login_path: String,
+	logout_path: String,
+	register_path: String,
+	#[cfg(feature = "template")]
+	login_page: Option<std::path::PathBuf>,
+	#[cfg(feature = "template")]
+	register_page: Option<std::path::PathBuf>,
+}
+
+impl AuthRoutes {
+	pub fn new(users: Arc<Users>) -> Self {
+		Self {
+			users,
+			login_path: String::from("/login"),
+			logout_path: String::from("/logout"),
+			register_path: String::from("/register"),
+			#[cfg(feature = "template")]
+			login_page: None,
+			#[cfg(feature = "template")]
+			register_page: None,
+		}
+	}
+
+	/// Defaults to `/login`
+	pub fn login_path<S: Into<String>>(mut self, path: S) -> Self {
+		self.login_path = path.into();
+		self
+	}
+
+	/// Defaults to `/logout`
+	pub fn logout_path<S: Into<String>>(mut self, path: S) -> Self {
+		self.logout_path = path.into();
+		self
+	}
+
+	/// Defaults to `/register`
+	pub fn register_path<S: Into<String>>(mut self, path: S) -> Self {
+		self.register_path = path.into();
+		self
+	}
+
+	/// Template file to serve at the login path
+	#[cfg(feature = "template")]
+	pub fn login_page<P: Into<std::path::PathBuf>>(mut self, path: P) -> Self {
+		self.login_page = Some(path.into());
+		self
+	}
+
+	/// Template file to serve at the register path
+	#[cfg(feature = "template")]
+	pub fn register_page<P: Into<std::path::PathBuf>>(mut self, path: P) -> Self {
+		self.register_page = Some(path.into());
+		self
+	}
+
+	pub fn into_router<S>(self) -> Router<S>
+	where
+		S: Clone + Send + Sync + 'static,
+	{
+		let login_path = self.login_path.clone();
+		let logout_path = self.logout_path.clone();
+		let register_path = self.register_path.clone();
+
+		Router::new()
+			.route(&login_path, get(login_page).post(login))
+			.route(&logout_path, post(logout))
+			.route(&register_path, get(register_page).post(register))
+			.with_state(Arc::new(self))
+	}
+
+	/// Respond to a failed post. Renders the page if we have one, otherwise the
+	/// error is sent as text.
+	#[allow(unused_variables)]
+	async fn failure(&self, page: Page, status: StatusCode, next: &str, error: &str) -> Response {
+		#[cfg(feature = "template")]
+		if let Some(path) = self.page(page) {
+			return render(path, status, next, Some(error)).await;
+		}
+
+		(status, error.to_owned()).into_response()
+	}
+
+	#[cfg(feature = "template")]
+	fn page(&self, page: Page) -> Option<&std::path::Path> {
+		match page {
+			Page::Login => self.login_page.as_deref(),
+			Page::Register => self.register_page.as_deref(),
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Page {
+	Login,
+	Register,
+}
+
+#[cfg(feature = "template")]
+async fn render(
+	path: &std::path::Path,
+	status: StatusCode,
+	next: &str,
+	error: Option<&str>,
+) -> Response {
+	let mut template = crate::template::Template::file(path).await;
+	template.set("next", html_escape(next));
+	template.set("error", html_escape(error.unwrap_or_default()));
+
+	match template.as_response() {
+		Ok(response) => (status, response).into_response(),
+		Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+	}
+}
+
+#[cfg(feature = "template")]
+fn html_escape(raw: &str) -> String {
+	let mut escaped = String::with_capacity(raw.len());
+	for c in raw.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+#[allow(unused_variables)]
+async fn show_page(routes: &AuthRoutes, page: Page, uri: &OriginalUri) -> Response {
+	#[cfg(feature = "template")]
+	if let Some(path) = routes.page(page) {
+		let next = safe_next(requested_next(uri, None).as_deref());
+		return render(path, StatusCode::OK, &next, None).await;
+	}
+
+	StatusCode::NOT_FOUND.into_response()
+}
+
+async fn login_page(State(routes): State<Arc<AuthRoutes>>, uri: OriginalUri) -> Response {
+	show_page(&routes, Page::Login, &uri).await
+}
+
+async fn register_page(State(routes): State<Arc<AuthRoutes>>, uri: OriginalUri) -> Response {
+	show_page(&routes, Page::Register, &uri).await
+}
+
+async fn login(
+	State(routes): State<Arc<AuthRoutes>>,
+	uri: OriginalUri,
+	FormBody(form): FormBody,
+) -> Response {
+	let next = safe_next(requested_next(&uri, Some(&form)).as_deref());
+
+	let (username, password) = match credentials(&form) {
+		Some(creds) => creds,
+		None => {
+			return routes
+				.failure(
+					Page::Login,
+					StatusCode::BAD_REQUEST,
+					&next,
+					"Missing username or password",
+				)
+				.await
+		}
+	};
+
+	match routes.users.login(username, password).await {
+		Some(session) => signed_in(session.login_cookie(), &next),
+		None => {
+			routes
+				.failure(
+					Page::Login,
+					StatusCode::UNAUTHORIZED,
+					&next,
+					"Incorrect username or password",
+				)
+				.await
+		}
+	}
+}
+
+async fn register(
+	State(routes): State<Arc<AuthRoutes>>,
+	uri: OriginalUri,
+	FormBody(form): FormBody,
+) -> Response {
+	let next = safe_next(requested_next(&uri, Some(&form)).as_deref());
+
+	let (username, password) = match credentials(&form) {
+		Some(creds) => creds,
+		None => {
+			return routes
+				.failure(
+					Page::Register,
+					StatusCode::BAD_REQUEST,
+					&next,
+					"Missing username or password",
+				)
+				.await
+		}
+	};
+
+	if !users::check_username(&username) {
+		let error = users::Error::InvalidUsername.to_string();
+		return routes
+			.failure(Page::Register, StatusCode::BAD_REQUEST, &next, &error)
+			.await;
+	}
+
+	let email = form
+		.get_first_value("email")
+		.filter(|email| !email.is_empty())
+		.map(<_>::to_owned);
+
+	match routes.users.register(email, username, password).await {
+		Ok(session) => signed_in(session.login_cookie(), &next),
+		Err(err) => {
+			routes
+				.failure(
+					Page::Register,
+					StatusCode::CONFLICT,
+					&next,
+					&err.to_string(),
+				)
+				.await
+		}
+	}
+}
+
+async fn logout(
+	State(routes): State<Arc<AuthRoutes>>,
+	sid: Result<SessionId, ()>,
+	uri: OriginalUri,
+	form: Result<FormBody, FormRejection>,
+) -> Response {
+	let form = form.ok().map(|FormBody(form)| form);
+	let next = safe_next(requested_next(&uri, form.as_ref()).as_deref());
+
+	match sid {
+		Ok(sid) => {
+			routes.users.logout(sid).await;
+			signed_in(users::session_removal_cookie(), &next)
+		}
+		Err(()) => Redirect::to(&next).into_response(),
+	}
+}
+
+/// Redirect to `next` while setting a cookie
+fn signed_in(cookie: String, next: &str) -> Response {
+	(
+		AppendHeaders([(header::SET_COOKIE, cookie)]),
+		Redirect::to(next),
+	)
+		.into_response()
+}
+
+fn credentials(form: &Query) -> Option<(String, String)> {
+	let username = form.get_first_value("username")?;
+	let password = form.get_first_value("password")?;
+
+	if username.is_empty() || password.is_empty() {
+		None
+	} else {
+		Some((username.to_owned(), password.to_owned()))
+	}
+}
+
+/// The `next` parameter from the form if it has one, or from the query string
+fn requested_next(uri: &OriginalUri, form: Option<&Query>) -> Option<String> {
+	if let Some(next) = form.and_then(|form| form.get_first_value("next")) {
+		return Some(next.to_owned());
+	}
+
+	let query: Query = uri.query()?.parse().ok()?;
+	query.get_first_value("next").map(<_>::to_owned)
+}
+
+/// Only follow `next` if it's a path on this site. Anything that a browser
+/// might read as another origin, like `//evil.example` or `/\evil.example`,
+/// becomes `/`.
+///
+/// `next` has already been decoded from the form or query, so anything that
+/// can't go in a Location header as-is, like non-ASCII, is percent-encoded
+/// again.
+fn safe_next(next: Option<&str>) -> String {
+	match next {
+		Some(next)
+			if next.starts_with('/')
+				&& !next.starts_with("//")
+				&& !next.starts_with("/\\")
+				&& !next.chars().any(|c| c.is_control()) =>
+		{
+			EncodeSet::Fragment.encode(next)
+		}
+		_ => String::from("/"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use axum::{
+		body::Body,
+		http::{header, Request, StatusCode},
+		response::IntoResponse,
+		routing::get,
+		Extension, Router,
+	};
+	use tower::ServiceExt;
+
+	use super::{login_redirect, safe_next, AuthRoutes, RequireLoginLayer};
+	use crate::{
+		query::Query,
+		users::{UserStub, Users},
+	};
+
+	fn app(users: Arc<Users>) -> Router {
+		Router::new()
+			.route(
+				"/secret",
+				get(|Extension(stub): Extension<UserStub>| async move { stub.username }),
+			)
+			.layer(RequireLoginLayer::new(users).login_path("/signin"))
+	}
+
+	#[tokio::test]
+	async fn redirects_browsers() {
+		let request = Request::get("/secret?page=2")
+			.header(header::ACCEPT, "text/html,application/xhtml+xml")
+			.body(Body::empty())
+			.unwrap();
+		let response = app(Arc::new(Users::new())).oneshot(request).await.unwrap();
+
+		assert_eq!(response.status(), StatusCode::SEE_OTHER);
+		assert_eq!(
+			response.headers()[header::LOCATION],
+			"/signin?next=%2fsecret%3fpage%3d2"
+		);
+	}
+
+	#[test]
+	fn next_keeps_its_query() {
+		let response = login_redirect("/login", "/a?b=1&c=2").into_response();
+		let location = response.headers()[header::LOCATION].to_str().unwrap();
+		assert_eq!(location, "/login?next=%2fa%3fb%3d1%26c%3d2");
+
+		let (_, query) = location.split_once('?').unwrap();
+		let query: Query = query.parse().unwrap();
+		assert_eq!(query.get_first_value("next"), Some("/a?b=1&c=2"));
+	}
+
+	#[tokio::test]
+	async fn rejects_api_requests() {
+		let request = Request::get("/secret")
+			.header(header::ACCEPT, "application/json")
+			.header(header::COOKIE, "sid=stale")
+			.body(Body::empty())
+			.unwrap();
+		let response = app(Arc::new(Users::new())).oneshot(request).await.unwrap();
+
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+	}
+
+	#[tokio::test]
+	async fn lets_sessions_through() {
+		let users = Arc::new(Users::new());
+		let session = users
+			.register(None, "gen".into(), "password".into())
+			.await
+			.unwrap();
+
+		let request = Request::get("/secret")
+			.header(header::COOKIE, format!("sid={}", session.sid))
+			.body(Body::empty())
+			.unwrap();
+		let response = app(users).oneshot(request).await.unwrap();
+
+		assert_eq!(response.status(), StatusCode::OK);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(&body[..], b"gen");
+	}
+
+	fn form(uri: &str, body: &str, cookie: Option<&str>) -> Request<Body> {
+		let mut request =
+			Request::post(uri).header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+		if let Some(cookie) = cookie {
+			request = request.header(header::COOKIE, cookie);
+		}
+		request.body(Body::from(body.to_owned())).unwrap()
+	}
+
+	#[tokio::test]
+	async fn register_login_logout() {
+		let users = Arc::new(Users::new());
+		let app: Router = AuthRoutes::new(users.clone()).into_router();
+
+		let response = app
+			.clone()
+			.oneshot(form(
+				"/register",
+				"username=gen&password=hunter2&next=%2Fhome",
+				None,
+			))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::SEE_OTHER);
+		assert_eq!(response.headers()[header::LOCATION], "/home");
+		assert!(users.stub_by_username("gen").await.is_some());
+
+		let response = app
+			.clone()
+			.oneshot(form("/login", "username=gen&password=wrong", None))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+		let response = app
+			.clone()
+			.oneshot(form(
+				"/login?next=https://evil.example",
+				"username=gen&password=hunter2",
+				None,
+			))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::SEE_OTHER);
+		assert_eq!(response.headers()[header::LOCATION], "/");
+
+		let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+		let sid = cookie.split(';').next().unwrap().to_owned();
+		assert!(users
+			.session_by_id(sid[4..].to_owned().into())
+			.await
+			.is_some());
+
+		let response = app.oneshot(form("/logout", "", Some(&sid))).await.unwrap();
+		assert_eq!(response.status(), StatusCode::SEE_OTHER);
+		let cleared = response.headers()[header::SET_COOKIE].to_str().unwrap();
+		assert!(cleared.starts_with("sid=;"));
+		// Logging out shouldn't send the session ID back out again
+		assert!(!cleared.contains(&sid[4..]));
+		assert!(users
+			.session_by_id(sid[4..].to_owned().into())
+			.await
+			.is_none());
+	}
+
+	#[test]
+	fn only_local_next() {
+		assert_eq!(safe_next(Some("/account?tab=1")), "/account?tab=1");
+		assert_eq!(safe_next(Some("//evil.example")), "/");
+		assert_eq!(safe_next(Some("/\\evil.example")), "/");
+		assert_eq!(safe_next(Some("https://evil.example/")), "/");
+		assert_eq!(safe_next(None), "/");
+		assert_eq!(safe_next(Some("/café s")), "/caf%C3%A9%20s");
+	}
+
+	#[tokio::test]
+	async fn non_ascii_next() {
+		let app: Router = AuthRoutes::new(Arc::new(Users::new())).into_router();
+
+		let response = app
+			.clone()
+			.oneshot(form("/logout?next=/caf%C3%A9", "", None))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::SEE_OTHER);
+		assert_eq!(response.headers()[header::LOCATION], "/caf%C3%A9");
+
+		let users = Arc::new(Users::new());
+		users
+			.register(None, "gen".into(), "hunter2".into())
+			.await
+			.unwrap();
+		let response = AuthRoutes::new(users)
+			.into_router()
+			.oneshot(form(
+				"/login",
+				"username=gen&password=hunter2&next=%2Fcaf%C3%A9",
+				None,
+			))
+			.await
+			.unwrap();
+		assert_eq!(response.status(), StatusCode::SEE_OTHER);
+		assert_eq!(response.headers()[header::LOCATION], "/caf%C3%A9");
+	}
+}
@@ -1,16 +1,40 @@
-#![allow(dead_code)]
-use std::{collections::HashMap, time::Duration};
+use std::{fmt, str::FromStr, time::Duration};
 
-use time::{macros::format_description, PrimitiveDateTime};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use thiserror::Error;
+use time::{macros::format_description, Date, Month, OffsetDateTime, Time, UtcOffset};
 
+use crate::query::{Query, QueryParseError};
+
+#[cfg(feature = "signed")]
+mod signed;
+
+#[cfg(feature = "signed")]
+pub use signed::SignedCookies;
+
+#[cfg(feature = "private")]
+mod private;
+
+#[cfg(feature = "private")]
+pub use private::PrivateCookies;
+
+/// The most a browser will store of one cookie, counting the name and the
+/// encoded value but not the attributes
+pub const MAX_COOKIE_SIZE: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct SetCookie {
 	key: String,
 	value: String,
-	expiration: Option<PrimitiveDateTime>,
+	encoding: Encoding,
+	expiration: Option<OffsetDateTime>,
 	max_age: Option<Duration>,
 	secure: bool,
 	httponly: bool,
 	path: Option<String>,
+	domain: Option<String>,
+	same_site: Option<SameSite>,
+	partitioned: bool,
 }
 
 impl SetCookie {
@@ -18,14 +42,71 @@ impl SetCookie {
 		Self {
 			key,
 			value,
+			encoding: Encoding::Percent,
 			expiration: None,
 			max_age: None,
 			secure: true,
 			httponly: true,
 			path: None,
+			domain: None,
+			same_site: None,
+			partitioned: false,
 		}
 	}
 
+	/// A cookie that tells the browser to delete the one with this name. It
+	/// has an empty value, `Max-Age=0`, and expired at the Unix epoch.
+	///
+	/// A browser only deletes the cookie if the Path and Domain match the ones
+	/// it was set with, so set those the same way, or use
+	/// [SetCookie::to_removal] on the cookie you set.
+	///
+	///```rust
+	///use mavourings::cookie::SetCookie;
+	///
+	///let removal = SetCookie::removal("theme").path(Some(String::from("/")));
+	///
+	///assert_eq!(
+	///    removal.as_string(),
+	///    "theme=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Secure; HttpOnly; Path=/"
+	///);
+	///```
+	pub fn removal<S: Into<String>>(key: S) -> Self {
+		Self::new(key.into(), String::new())
+			.expires(Some(OffsetDateTime::UNIX_EPOCH))
+			.max_age(Some(Duration::ZERO))
+	}
+
+	/// A [removal](SetCookie::removal) for this cookie. The Path and Domain are
+	/// copied so the browser finds the cookie to delete, and so are Secure and
+	/// Partitioned, which a `__Secure-` or partitioned cookie needs.
+	///
+	///```rust
+	///use mavourings::cookie::SetCookie;
+	///
+	///let cookie = SetCookie::new("theme".into(), "dark".into())
+	///    .path(Some(String::from("/app")))
+	///    .domain(Some(String::from("example.com")));
+	///
+	///assert_eq!(
+	///    cookie.to_removal().as_string(),
+	///    "theme=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Secure; HttpOnly; Path=/app; Domain=example.com"
+	///);
+	///```
+	pub fn to_removal(&self) -> Self {
+		Self::removal(self.key.clone())
+			.secure(self.secure)
+			.partitioned(self.partitioned)
+			.path(self.path.clone())
+			.domain(self.domain.clone())
+	}
+
+	/// How the value is written to the header. Defaults to [Encoding::Percent]
+	pub fn encoding(mut self, encoding: Encoding) -> Self {
+		self.encoding = encoding;
+		self
+	}
+
 	pub fn secure(mut self, flag: bool) -> Self {
 		self.secure = flag;
 		self
@@ -36,6 +117,13 @@ impl SetCookie {
 		self
 	}
 
+	/// When the cookie expires. It's converted to GMT when written, so any
+	/// offset is fine.
+	pub fn expires(mut self, expiration: Option<OffsetDateTime>) -> Self {
+		self.expiration = expiration;
+		self
+	}
+
 	pub fn max_age(mut self, seconds: Option<Duration>) -> Self {
 		self.max_age = seconds;
 		self
@@ -46,11 +134,142 @@ impl SetCookie {
 		self
 	}
 
+	pub fn domain(mut self, domain: Option<String>) -> Self {
+		self.domain = domain;
+		self
+	}
+
+	pub fn same_site(mut self, same_site: Option<SameSite>) -> Self {
+		self.same_site = same_site;
+		self
+	}
+
+	/// Partitioned cookies (CHIPS) are kept separately for each top-level site
+	/// they're used under. They must also be Secure.
+	pub fn partitioned(mut self, flag: bool) -> Self {
+		self.partitioned = flag;
+		self
+	}
+
+	pub fn key(&self) -> &str {
+		&self.key
+	}
+
+	/// The value before it's encoded
+	pub fn value(&self) -> &str {
+		&self.value
+	}
+
+	pub fn get_encoding(&self) -> Encoding {
+		self.encoding
+	}
+
+	pub fn get_expires(&self) -> Option<OffsetDateTime> {
+		self.expiration
+	}
+
+	pub fn get_max_age(&self) -> Option<Duration> {
+		self.max_age
+	}
+
+	pub fn is_secure(&self) -> bool {
+		self.secure
+	}
+
+	pub fn is_httponly(&self) -> bool {
+		self.httponly
+	}
+
+	pub fn get_path(&self) -> Option<&str> {
+		self.path.as_deref()
+	}
+
+	pub fn get_domain(&self) -> Option<&str> {
+		self.domain.as_deref()
+	}
+
+	pub fn get_same_site(&self) -> Option<SameSite> {
+		self.same_site
+	}
+
+	pub fn is_partitioned(&self) -> bool {
+		self.partitioned
+	}
+
+	/// Check the attributes make sense together, the way browsers will when
+	/// they get the cookie. A cookie that fails this would be ignored.
+	///
+	/// - the name must be an HTTP token
+	/// - a value written with [Encoding::Raw] must only have cookie octets
+	/// - Path and Domain can't have control characters or `;`
+	/// - `SameSite=None` and `Partitioned` need `Secure`
+	/// - names starting `__Secure-` need `Secure`
+	/// - names starting `__Host-` need `Secure`, `Path=/`, and no `Domain`
+	/// - the name and encoded value must fit in [MAX_COOKIE_SIZE] bytes
+	pub fn validate(&self) -> Result<(), SetCookieError> {
+		if self.key.is_empty() || !self.key.bytes().all(is_token) {
+			return Err(SetCookieError::InvalidName(self.key.clone()));
+		}
+
+		if self.encoding == Encoding::Raw && !self.value.bytes().all(is_cookie_octet) {
+			return Err(SetCookieError::InvalidValue);
+		}
+
+		let bad_attribute = |value: &Option<String>| {
+			value
+				.as_deref()
+				.map(|v| v.chars().any(|c| c.is_control() || c == ';'))
+				.unwrap_or(false)
+		};
+
+		if bad_attribute(&self.path) {
+			return Err(SetCookieError::InvalidAttribute("Path"));
+		}
+
+		if bad_attribute(&self.domain) {
+			return Err(SetCookieError::InvalidAttribute("Domain"));
+		}
+
+		if self.same_site == Some(SameSite::None) && !self.secure {
+			return Err(SetCookieError::InsecureSameSiteNone);
+		}
+
+		if self.partitioned && !self.secure {
+			return Err(SetCookieError::InsecurePartitioned);
+		}
+
+		if has_prefix(&self.key, "__Secure-") && !self.secure {
+			return Err(SetCookieError::SecurePrefix);
+		}
+
+		if has_prefix(&self.key, "__Host-")
+			&& (!self.secure || self.path.as_deref() != Some("/") || self.domain.is_some())
+		{
+			return Err(SetCookieError::HostPrefix);
+		}
+
+		let size = self.key.len() + self.encoding.encode(&self.value).len();
+		if size > MAX_COOKIE_SIZE {
+			return Err(SetCookieError::TooLarge(size));
+		}
+
+		Ok(())
+	}
+
+	/// [SetCookie::validate] and then [SetCookie::as_string]
+	pub fn try_as_string(&self) -> Result<String, SetCookieError> {
+		self.validate()?;
+		Ok(self.as_string())
+	}
+
+	/// The value of a Set-Cookie header. This doesn't check the cookie, see
+	/// [SetCookie::try_as_string] for that.
 	pub fn as_string(&self) -> String {
-		let mut cookie = format!("{}={}", self.key, self.value);
+		let mut cookie = format!("{}={}", self.key, self.encoding.encode(&self.value));
 
 		if let Some(expiration) = self.expiration {
 			let format = format_description!("[weekday repr:short], [day] [month repr:short] [year] [hour repr:24]:[minute]:[second] GMT");
+			let expiration = expiration.to_offset(UtcOffset::UTC);
 			cookie.push_str(&format!("; Expires={}", expiration.format(format).unwrap()))
 		}
 
@@ -70,21 +289,733 @@ impl SetCookie {
 			cookie.push_str(&format!("; Path={path}"))
 		}
 
+		if let Some(domain) = &self.domain {
+			cookie.push_str(&format!("; Domain={domain}"))
+		}
+
+		if let Some(same_site) = self.same_site {
+			cookie.push_str(&format!("; SameSite={same_site}"))
+		}
+
+		if self.partitioned {
+			cookie.push_str("; Partitioned")
+		}
+
 		cookie
 	}
 }
 
-pub fn parse_header(string: &str) -> Result<HashMap<&str, &str>, ()> {
-	let mut cookies = HashMap::new();
+impl FromStr for SetCookie {
+	type Err = CookieParseError;
+
+	/// Parse the value of a Set-Cookie header the way RFC 6265 says a browser
+	/// should. Attributes we don't understand, or that have values we can't
+	/// parse, are ignored. Unlike [SetCookie::new], Secure and HttpOnly are
+	/// only set if they're present.
+	///
+	/// The value is percent-decoded if it's exactly what [Encoding::Percent]
+	/// would have written. Otherwise it's kept as it was, with [Encoding::Raw],
+	/// so writing the cookie back out gives the same header.
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::cookie::{SameSite, SetCookie};
+	///
+	///let header = "id=a3fWa; Expires=Wed, 21 Oct 2015 07:28:00 GMT; SameSite=Lax; Secure";
+	///let cookie: SetCookie = header.parse().unwrap();
+	///
+	///assert_eq!(cookie.key(), "id");
+	///assert_eq!(cookie.get_expires().unwrap().unix_timestamp(), 1445412480);
+	///assert_eq!(cookie.get_same_site(), Some(SameSite::Lax));
+	///assert!(cookie.is_secure());
+	///assert!(!cookie.is_httponly());
+	///```
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (pair, attributes) = s.split_once(';').unwrap_or((s, ""));
+
+		let (key, value) = pair
+			.split_once('=')
+			.ok_or(CookieParseError::MissingEquals)?;
+		let (key, value) = (trim(key), trim(value));
 
-	for pair in string.split(";") {
-		match pair.split_once("=") {
-			None => return Err(()),
-			Some((key, value)) => {
-				cookies.insert(key.trim(), value.trim());
+		if key.is_empty() {
+			return Err(CookieParseError::EmptyName);
+		}
+
+		let cookie = match Encoding::Percent.decode(key, value) {
+			Ok(decoded) if Encoding::Percent.encode(&decoded) == value => {
+				SetCookie::new(key.to_owned(), decoded)
+			}
+			_ => SetCookie::new(key.to_owned(), value.to_owned()).encoding(Encoding::Raw),
+		};
+		let mut cookie = cookie.secure(false).httponly(false);
+
+		for attribute in attributes.split(';') {
+			let (name, value) = match attribute.split_once('=') {
+				Some((name, value)) => (trim(name), trim(value)),
+				None => (trim(attribute), ""),
+			};
+
+			match name.to_ascii_lowercase().as_str() {
+				"expires" => {
+					if let Some(expiration) = parse_cookie_date(value) {
+						cookie.expiration = Some(expiration);
+					}
+				}
+				"max-age" => {
+					if let Some(max_age) = parse_max_age(value) {
+						cookie.max_age = Some(max_age);
+					}
+				}
+				"domain" => {
+					let domain = value.strip_prefix('.').unwrap_or(value);
+					if !domain.is_empty() {
+						cookie.domain = Some(domain.to_owned());
+					}
+				}
+				"path" if value.starts_with('/') => cookie.path = Some(value.to_owned()),
+				"samesite" => {
+					if let Ok(same_site) = value.parse() {
+						cookie.same_site = Some(same_site);
+					}
+				}
+				"secure" => cookie.secure = true,
+				"httponly" => cookie.httponly = true,
+				"partitioned" => cookie.partitioned = true,
+				_ => (),
 			}
 		}
+
+		Ok(cookie)
 	}
+}
+
+/// Max-Age is a count of seconds. Zero or less means the cookie is already
+/// expired, we call that zero.
+fn parse_max_age(value: &str) -> Option<Duration> {
+	let (negative, digits) = match value.strip_prefix('-') {
+		Some(digits) => (true, digits),
+		None => (false, value),
+	};
 
-	Ok(cookies)
+	if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+
+	if negative {
+		return Some(Duration::ZERO);
+	}
+
+	// Too many digits to fit is still a very long time
+	Some(Duration::from_secs(digits.parse().unwrap_or(u64::MAX)))
+}
+
+/// The date parsing algorithm from RFC 6265 section 5.1.1. It finds the time,
+/// day, month, and year wherever they are, so it reads IMF-fixdate, the
+/// obsolete RFC 850 and asctime formats, and the other things servers send.
+fn parse_cookie_date(date: &str) -> Option<OffsetDateTime> {
+	const MONTHS: [&str; 12] = [
+		"jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+	];
+
+	let is_delimiter = |c: char| matches!(c, '\x09' | '\x20'..='\x2F' | '\x3B'..='\x40' | '\x5B'..='\x60' | '\x7B'..='\x7E');
+
+	// The number made by the digits at the start of the token, if there are
+	// between `min` and `max` of them
+	let leading_digits = |token: &str, min: usize, max: usize| -> Option<u32> {
+		let count = token.bytes().take_while(u8::is_ascii_digit).count();
+		if count < min || count > max {
+			return None;
+		}
+		token[..count].parse().ok()
+	};
+
+	let mut time = None;
+	let mut day = None;
+	let mut month = None;
+	let mut year = None;
+
+	for token in date.split(is_delimiter).filter(|token| !token.is_empty()) {
+		if time.is_none() {
+			let mut fields = token.splitn(3, ':');
+			if let (Some(h), Some(m), Some(s)) = (fields.next(), fields.next(), fields.next()) {
+				let whole =
+					|f: &str| (1..=2).contains(&f.len()) && f.bytes().all(|b| b.is_ascii_digit());
+
+				if whole(h) && whole(m) {
+					if let Some(s) = leading_digits(s, 1, 2) {
+						time = Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?, s));
+						continue;
+					}
+				}
+			}
+		}
+
+		if day.is_none() {
+			if let Some(d) = leading_digits(token, 1, 2) {
+				day = Some(d);
+				continue;
+			}
+		}
+
+		if month.is_none() {
+			// Tokens can be any UTF-8, so don't slice through a character
+			if let Some(name) = token.get(..3).map(str::to_ascii_lowercase) {
+				if let Some(idx) = MONTHS.iter().position(|m| *m == name) {
+					month = Some(idx as u8 + 1);
+					continue;
+				}
+			}
+		}
+
+		if year.is_none() {
+			if let Some(y) = leading_digits(token, 2, 4) {
+				year = Some(y);
+				continue;
+			}
+		}
+	}
+
+	let (hour, minute, second) = time?;
+	let year = match year? {
+		y @ 70..=99 => y + 1900,
+		y @ 0..=69 => y + 2000,
+		y => y,
+	};
+
+	if year < 1601 || hour > 23 || minute > 59 || second > 59 {
+		return None;
+	}
+
+	let date = Date::from_calendar_date(
+		year as i32,
+		Month::try_from(month?).ok()?,
+		u8::try_from(day?).ok()?,
+	)
+	.ok()?;
+	let time = Time::from_hms(hour as u8, minute as u8, second as u8).ok()?;
+
+	Some(date.with_time(time).assume_utc())
+}
+
+/// The characters allowed in an HTTP token, which cookie names must be
+fn is_token(b: u8) -> bool {
+	b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// The characters RFC 6265 allows in a cookie value. No controls, spaces,
+/// double quotes, commas, semicolons, or backslashes.
+fn is_cookie_octet(b: u8) -> bool {
+	matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+/// Browsers match cookie prefixes without regard to case
+fn has_prefix(name: &str, prefix: &str) -> bool {
+	name.len() >= prefix.len()
+		&& name.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+/// How a cookie's value is written to the Set-Cookie header. Use the same
+/// encoding with [CookieJar::get_decoded] to read it back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+	/// Percent-encode the bytes that aren't allowed in a cookie, and `%`.
+	/// Values that were already safe are written unchanged.
+	#[default]
+	Percent,
+	/// URL-safe base64 without padding. Everything's encoded, so it's bigger,
+	/// but the value is opaque to anything that looks at it.
+	Base64,
+	/// Write the value as it is. [SetCookie::validate] fails if it has
+	/// anything that isn't allowed in a cookie.
+	Raw,
+}
+
+impl Encoding {
+	pub fn encode(&self, value: &str) -> String {
+		match self {
+			Encoding::Percent => {
+				let mut encoded = String::with_capacity(value.len());
+				for b in value.bytes() {
+					if is_cookie_octet(b) && b != b'%' {
+						encoded.push(b as char);
+					} else {
+						encoded.push_str(&format!("%{b:02X}"));
+					}
+				}
+				encoded
+			}
+			Encoding::Base64 => URL_SAFE_NO_PAD.encode(value),
+			Encoding::Raw => value.to_owned(),
+		}
+	}
+
+	/// Undo [Encoding::encode]. The name is only used in the error.
+	pub fn decode(&self, name: &str, value: &str) -> Result<String, CookieParseError> {
+		match self {
+			Encoding::Percent => {
+				Query::url_decode(value, false).map_err(|source| CookieParseError::Decode {
+					name: name.to_owned(),
+					source,
+				})
+			}
+			Encoding::Base64 => URL_SAFE_NO_PAD
+				.decode(value)
+				.ok()
+				.and_then(|bytes| String::from_utf8(bytes).ok())
+				.ok_or_else(|| CookieParseError::Base64 {
+					name: name.to_owned(),
+				}),
+			Encoding::Raw => Ok(value.to_owned()),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+	Strict,
+	Lax,
+	/// Requires the cookie be Secure
+	None,
+}
+
+impl fmt::Display for SameSite {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SameSite::Strict => write!(f, "Strict"),
+			SameSite::Lax => write!(f, "Lax"),
+			SameSite::None => write!(f, "None"),
+		}
+	}
+}
+
+impl FromStr for SameSite {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_ascii_lowercase().as_str() {
+			"strict" => Ok(SameSite::Strict),
+			"lax" => Ok(SameSite::Lax),
+			"none" => Ok(SameSite::None),
+			_ => Err(()),
+		}
+	}
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SetCookieError {
+	#[error("the cookie name {0:?} is empty or has characters that aren't allowed")]
+	InvalidName(String),
+	#[error("the cookie value has characters that aren't allowed; use Encoding::Percent or Encoding::Base64")]
+	InvalidValue,
+	#[error("the {0} attribute has a control character or a ';'")]
+	InvalidAttribute(&'static str),
+	#[error("the cookie's name and value are {0} bytes, more than a browser will store")]
+	TooLarge(usize),
+	#[error("SameSite=None cookies must be Secure")]
+	InsecureSameSiteNone,
+	#[error("Partitioned cookies must be Secure")]
+	InsecurePartitioned,
+	#[error("cookies with the __Secure- prefix must be Secure")]
+	SecurePrefix,
+	#[error("cookies with the __Host- prefix must be Secure, have Path=/, and no Domain")]
+	HostPrefix,
+}
+
+/// The cookies from a request's Cookie header, in the order they were sent.
+/// A name can appear more than once; browsers send the cookie with the most
+/// specific path first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CookieJar {
+	cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+	/// The value of the first cookie with this name
+	pub fn get<S: AsRef<str>>(&self, name: S) -> Option<&str> {
+		self.get_all(name).next()
+	}
+
+	/// The value of the first cookie with this name, decoded. Use the
+	/// [Encoding] it was set with.
+	pub fn get_decoded<S: AsRef<str>>(
+		&self,
+		name: S,
+		encoding: Encoding,
+	) -> Result<Option<String>, CookieParseError> {
+		let name = name.as_ref();
+		self.get(name)
+			.map(|value| encoding.decode(name, value))
+			.transpose()
+	}
+
+	/// The values of every cookie with this name, in the order they were sent
+	pub fn get_all<S: AsRef<str>>(&self, name: S) -> impl Iterator<Item = &str> {
+		self.cookies
+			.iter()
+			.filter(move |(key, _)| key == name.as_ref())
+			.map(|(_, value)| value.as_str())
+	}
+
+	/// Every `(name, value)` pair in the order they were sent
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.cookies
+			.iter()
+			.map(|(key, value)| (key.as_str(), value.as_str()))
+	}
+
+	pub fn len(&self) -> usize {
+		self.cookies.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cookies.is_empty()
+	}
+}
+
+impl FromIterator<(String, String)> for CookieJar {
+	fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+		Self {
+			cookies: iter.into_iter().collect(),
+		}
+	}
+}
+
+impl IntoIterator for CookieJar {
+	type Item = (String, String);
+
+	type IntoIter = std::vec::IntoIter<(String, String)>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.cookies.into_iter()
+	}
+}
+
+/// Parse the value of a Cookie header as described in RFC 6265. Pairs that
+/// don't have an `=` or have an empty name are skipped rather than failing
+/// the whole header, and values wrapped in double quotes have them removed.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::cookie::parse_header;
+///
+///let jar = parse_header("sid=abc; theme=\"dark\"; broken; sid=def");
+///
+///assert_eq!(jar.get("sid"), Some("abc"));
+///assert_eq!(jar.get_all("sid").collect::<Vec<_>>(), vec!["abc", "def"]);
+///assert_eq!(jar.get("theme"), Some("dark"));
+///assert_eq!(jar.len(), 3);
+///```
+pub fn parse_header(header: &str) -> CookieJar {
+	let mut cookies = vec![];
+
+	for pair in header.split(';') {
+		let (name, value) = match pair.split_once('=') {
+			None => continue,
+			Some((name, value)) => (trim(name), trim(value)),
+		};
+
+		if name.is_empty() {
+			continue;
+		}
+
+		let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+			Some(unquoted) => unquoted,
+			None => value,
+		};
+
+		cookies.push((name.to_owned(), value.to_owned()));
+	}
+
+	CookieJar { cookies }
+}
+
+/// Like [parse_header] but percent-decodes the values, for cookies that were
+/// percent-encoded when they were set.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::cookie::parse_header_decoded;
+///
+///let jar = parse_header_decoded("greeting=hello%2C%20world").unwrap();
+///
+///assert_eq!(jar.get("greeting"), Some("hello, world"));
+///```
+pub fn parse_header_decoded(header: &str) -> Result<CookieJar, CookieParseError> {
+	let mut jar = parse_header(header);
+
+	for (name, value) in jar.cookies.iter_mut() {
+		*value = Encoding::Percent.decode(name, value)?;
+	}
+
+	Ok(jar)
+}
+
+/// Cookie headers can be folded with whitespace but only spaces and tabs count
+fn trim(s: &str) -> &str {
+	s.trim_matches(|c| c == ' ' || c == '\t')
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CookieParseError {
+	#[error("the cookie has no '=' between its name and value")]
+	MissingEquals,
+	#[error("the cookie has an empty name")]
+	EmptyName,
+	#[error("the value of the cookie {name} could not be decoded")]
+	Decode {
+		name: String,
+		source: QueryParseError,
+	},
+	#[error("the value of the cookie {name} isn't base64 encoded UTF-8")]
+	Base64 { name: String },
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use time::macros::datetime;
+
+	use super::{
+		parse_cookie_date, parse_header, parse_header_decoded, CookieParseError, Encoding,
+		SameSite, SetCookie, SetCookieError, MAX_COOKIE_SIZE,
+	};
+	use crate::query::QueryParseError;
+
+	#[test]
+	fn set_cookie_attributes() {
+		let cookie = SetCookie::new("id".into(), "a3fWa".into())
+			.expires(Some(datetime!(2015-10-21 09:28 -2)))
+			.max_age(Some(Duration::from_secs(3600)))
+			.path(Some("/docs".into()))
+			.domain(Some("example.com".into()))
+			.same_site(Some(SameSite::Lax))
+			.partitioned(true);
+
+		assert_eq!(
+			cookie.try_as_string().unwrap(),
+			"id=a3fWa; Expires=Wed, 21 Oct 2015 11:28:00 GMT; Max-Age=3600; Secure; HttpOnly; Path=/docs; Domain=example.com; SameSite=Lax; Partitioned"
+		);
+	}
+
+	#[test]
+	fn set_cookie_round_trip() {
+		let cookie = SetCookie::new("id".into(), "a3fWa".into())
+			.expires(Some(datetime!(2015-10-21 09:28 -2)))
+			.max_age(Some(Duration::from_secs(3600)))
+			.path(Some("/docs".into()))
+			.domain(Some("example.com".into()))
+			.same_site(Some(SameSite::Strict))
+			.partitioned(true);
+		assert_eq!(cookie.as_string().parse::<SetCookie>().unwrap(), cookie);
+
+		let bare = SetCookie::new("a".into(), "".into())
+			.secure(false)
+			.httponly(false);
+		assert_eq!(bare.as_string().parse::<SetCookie>().unwrap(), bare);
+	}
+
+	#[test]
+	fn set_cookie_parse() {
+		let cookie: SetCookie =
+			"  lang = en-US ;path=/a;PATH=/b; max-age=-5; DOMAIN=.Example.com; samesite=bogus; httponly; unknown=1"
+				.parse()
+				.unwrap();
+
+		assert_eq!(cookie.key(), "lang");
+		assert_eq!(cookie.value(), "en-US");
+		assert_eq!(cookie.get_path(), Some("/b"));
+		assert_eq!(cookie.get_max_age(), Some(Duration::ZERO));
+		assert_eq!(cookie.get_domain(), Some("Example.com"));
+		assert_eq!(cookie.get_same_site(), None);
+		assert!(cookie.is_httponly());
+		assert!(!cookie.is_secure());
+
+		assert_eq!(
+			"noequals; Secure".parse::<SetCookie>(),
+			Err(CookieParseError::MissingEquals)
+		);
+		assert_eq!(
+			" =value".parse::<SetCookie>(),
+			Err(CookieParseError::EmptyName)
+		);
+	}
+
+	#[test]
+	fn cookie_dates() {
+		let expected = Some(datetime!(1994-11-06 08:49:37 UTC));
+
+		// IMF-fixdate, RFC 850, and asctime
+		assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+		assert_eq!(
+			parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+			expected
+		);
+		assert_eq!(parse_cookie_date("Sun Nov  6 08:49:37 1994"), expected);
+		assert_eq!(parse_cookie_date("Sun, 6-Nov-1994 8:49:37 GMT"), expected);
+
+		assert_eq!(
+			parse_cookie_date("Thu, 01 Jan 70 00:00:00 GMT"),
+			Some(datetime!(1970-01-01 00:00 UTC))
+		);
+		assert_eq!(
+			parse_cookie_date("Fri, 31 Dec 38 23:59:59 GMT"),
+			Some(datetime!(2038-12-31 23:59:59 UTC))
+		);
+
+		assert_eq!(parse_cookie_date("Sun, 31 Feb 1994 08:49:37 GMT"), None);
+		assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+		assert_eq!(parse_cookie_date("Sun, 06 Nov 1994"), None);
+		assert_eq!(parse_cookie_date("tomorrow"), None);
+	}
+
+	#[test]
+	fn cookie_dates_non_ascii() {
+		assert_eq!(parse_cookie_date("éé"), None);
+		assert_eq!(parse_cookie_date("Sun, 06 Noé 1994 08:49:37 GMT"), None);
+		assert_eq!(
+			parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT ü"),
+			Some(datetime!(1994-11-06 08:49:37 UTC))
+		);
+
+		let cookie: SetCookie = "a=b; Expires=éé".parse().unwrap();
+		assert_eq!(cookie.get_expires(), None);
+	}
+
+	#[test]
+	fn set_cookie_validation() {
+		let insecure = |name: &str| SetCookie::new(name.into(), "v".into()).secure(false);
+
+		assert_eq!(
+			insecure("a").same_site(Some(SameSite::None)).validate(),
+			Err(SetCookieError::InsecureSameSiteNone)
+		);
+		assert_eq!(
+			insecure("a").partitioned(true).validate(),
+			Err(SetCookieError::InsecurePartitioned)
+		);
+		assert_eq!(
+			insecure("__secure-a").validate(),
+			Err(SetCookieError::SecurePrefix)
+		);
+		assert_eq!(
+			SetCookie::new("__Host-a".into(), "v".into())
+				.path(Some("/".into()))
+				.domain(Some("example.com".into()))
+				.validate(),
+			Err(SetCookieError::HostPrefix)
+		);
+		assert_eq!(
+			SetCookie::new("__Host-a".into(), "v".into())
+				.path(Some("/".into()))
+				.same_site(Some(SameSite::None))
+				.validate(),
+			Ok(())
+		);
+	}
+
+	#[test]
+	fn parse_lenient() {
+		let jar = parse_header(" a=1;b = 2 ;;noequals; =nameless;\tc=\"quoted value\"; d=; a=3");
+
+		assert_eq!(
+			jar.iter().collect::<Vec<_>>(),
+			vec![
+				("a", "1"),
+				("b", "2"),
+				("c", "quoted value"),
+				("d", ""),
+				("a", "3")
+			]
+		);
+	}
+
+	#[test]
+	fn parse_values_with_equals() {
+		let jar = parse_header("token=abc==; lone=\"");
+
+		assert_eq!(jar.get("token"), Some("abc=="));
+		assert_eq!(jar.get("lone"), Some("\""));
+	}
+
+	#[test]
+	fn parse_decoded() {
+		let jar = parse_header_decoded("a=%F0%9F%A5%BA; b=plus+stays").unwrap();
+		assert_eq!(jar.get("a"), Some("🥺"));
+		assert_eq!(jar.get("b"), Some("plus+stays"));
+
+		assert_eq!(
+			parse_header_decoded("ok=1; bad=%FF"),
+			Err(CookieParseError::Decode {
+				name: String::from("bad"),
+				source: QueryParseError::InvalidUtf8
+			})
+		);
+	}
+
+	#[test]
+	fn value_encoding() {
+		let value = "a; b=\"ü\" 100%";
+
+		let percent = SetCookie::new("v".into(), value.into());
+		assert_eq!(
+			percent.try_as_string().unwrap(),
+			"v=a%3B%20b=%22%C3%BC%22%20100%25; Secure; HttpOnly"
+		);
+		assert_eq!(percent.as_string().parse::<SetCookie>().unwrap(), percent);
+
+		let base64 = percent.clone().encoding(Encoding::Base64);
+		let header = base64.try_as_string().unwrap();
+		let sent = header.split(';').next().unwrap();
+		assert_eq!(
+			parse_header(sent).get_decoded("v", Encoding::Base64),
+			Ok(Some(String::from(value)))
+		);
+
+		let raw = percent.encoding(Encoding::Raw);
+		assert_eq!(raw.validate(), Err(SetCookieError::InvalidValue));
+
+		// Not what we'd have written, so it's kept as-is
+		let odd: SetCookie = "v=%e2%9c%93".parse().unwrap();
+		assert_eq!(odd.get_encoding(), Encoding::Raw);
+		assert_eq!(odd.as_string(), "v=%e2%9c%93");
+	}
+
+	#[test]
+	fn set_cookie_limits() {
+		for name in ["", "a b", "semi;colon", "é"] {
+			assert_eq!(
+				SetCookie::new(name.into(), "v".into()).validate(),
+				Err(SetCookieError::InvalidName(name.into()))
+			);
+		}
+
+		let path = SetCookie::new("a".into(), "b".into()).path(Some("/; Domain=evil".into()));
+		assert_eq!(
+			path.validate(),
+			Err(SetCookieError::InvalidAttribute("Path"))
+		);
+
+		// Attributes don't count towards the limit
+		let fits = SetCookie::new("a".into(), "b".repeat(MAX_COOKIE_SIZE - 1))
+			.path(Some(String::from("/long/path")));
+		assert!(fits.validate().is_ok());
+
+		let big = SetCookie::new("a".into(), "b".repeat(MAX_COOKIE_SIZE));
+		assert_eq!(
+			big.validate(),
+			Err(SetCookieError::TooLarge(MAX_COOKIE_SIZE + 1))
+		);
+
+		// but encoding does
+		let encoded = SetCookie::new("a".into(), " ".repeat(MAX_COOKIE_SIZE / 3 + 1));
+		assert!(matches!(
+			encoded.validate(),
+			Err(SetCookieError::TooLarge(_))
+		));
+	}
 }
@@ -0,0 +1,161 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+	aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+	XChaCha20Poly1305, XNonce,
+};
+
+use super::{CookieJar, Encoding, SetCookie};
+
+/// Length of an XChaCha20 nonce
+const NONCE_LENGTH: usize = 24;
+
+/// Encrypts cookie values with XChaCha20-Poly1305 so the client can neither
+/// read nor change them. The cookie's name is authenticated along with the
+/// value, so a value can't be moved to a different cookie.
+///
+/// An encrypted value is the base64url of a random nonce followed by the
+/// ciphertext.
+///
+/// For rotating keys, the newest key encrypts and the older ones, added with
+/// [PrivateCookies::decrypt_with], can still decrypt until you drop them.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::cookie::{parse_header, PrivateCookies, SetCookie};
+///
+///let crypter = PrivateCookies::new(*b"a secret key that is 32 bytes!!!");
+///let cookie = crypter.encrypt(SetCookie::new("cart".into(), "3 apples".into()));
+///assert!(!cookie.value().contains("apples"));
+///
+///let jar = parse_header(&format!("cart={}", cookie.value()));
+///assert_eq!(crypter.get(&jar, "cart").as_deref(), Some("3 apples"));
+///```
+#[derive(Clone)]
+pub struct PrivateCookies {
+	ciphers: Vec<XChaCha20Poly1305>,
+}
+
+impl PrivateCookies {
+	/// The key encrypts new cookies. It should be random and kept secret.
+	pub fn new(key: [u8; 32]) -> Self {
+		Self {
+			ciphers: vec![XChaCha20Poly1305::new(&key.into())],
+		}
+	}
+
+	/// Also decrypt cookies encrypted with this key, but don't encrypt with it
+	pub fn decrypt_with(mut self, key: [u8; 32]) -> Self {
+		self.ciphers.push(XChaCha20Poly1305::new(&key.into()));
+		self
+	}
+
+	/// Replace the value of the cookie with an encrypted one
+	pub fn encrypt(&self, mut cookie: SetCookie) -> SetCookie {
+		cookie.value = self.encrypt_value(&cookie.key, &cookie.value);
+		// Already base64, encoding it again would only get in the way
+		cookie.encoding = Encoding::Raw;
+		cookie
+	}
+
+	/// The encrypted version of a value for the cookie with this name
+	pub fn encrypt_value(&self, name: &str, value: &str) -> String {
+		let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let payload = Payload {
+			msg: value.as_bytes(),
+			aad: name.as_bytes(),
+		};
+
+		let ciphertext = self.ciphers[0]
+			.encrypt(&nonce, payload)
+			.expect("a cookie is nowhere near too long to encrypt");
+
+		let mut sealed = nonce.to_vec();
+		sealed.extend_from_slice(&ciphertext);
+		URL_SAFE_NO_PAD.encode(sealed)
+	}
+
+	/// Decrypt a value from the cookie with this name. Returns `None` if none
+	/// of our keys encrypted it, or if it was changed.
+	pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+		let sealed = URL_SAFE_NO_PAD.decode(encrypted).ok()?;
+		if sealed.len() < NONCE_LENGTH {
+			return None;
+		}
+
+		let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+		let nonce = XNonce::from_slice(nonce);
+
+		self.ciphers.iter().find_map(|cipher| {
+			let payload = Payload {
+				msg: ciphertext,
+				aad: name.as_bytes(),
+			};
+
+			let plaintext = cipher.decrypt(nonce, payload).ok()?;
+			String::from_utf8(plaintext).ok()
+		})
+	}
+
+	/// The decrypted value of the first cookie with this name that decrypts
+	pub fn get(&self, jar: &CookieJar, name: &str) -> Option<String> {
+		jar.get_all(name)
+			.find_map(|encrypted| self.decrypt(name, encrypted))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+	use super::PrivateCookies;
+	use crate::cookie::parse_header;
+
+	const KEY: [u8; 32] = *b"0123456789abcdef0123456789abcdef";
+	const OLD_KEY: [u8; 32] = *b"fedcba9876543210fedcba9876543210";
+
+	#[test]
+	fn round_trip() {
+		let crypter = PrivateCookies::new(KEY);
+		let one = crypter.encrypt_value("secret", "🥺 value; with = things");
+		let two = crypter.encrypt_value("secret", "🥺 value; with = things");
+
+		// The nonce is random, so the same value encrypts differently
+		assert_ne!(one, two);
+		assert_eq!(
+			crypter.decrypt("secret", &one).as_deref(),
+			Some("🥺 value; with = things")
+		);
+	}
+
+	#[test]
+	fn tampering() {
+		let crypter = PrivateCookies::new(KEY);
+		let encrypted = crypter.encrypt_value("secret", "value");
+
+		let mut sealed = URL_SAFE_NO_PAD.decode(&encrypted).unwrap();
+		let last = sealed.len() - 1;
+		sealed[last] ^= 1;
+		let tampered = URL_SAFE_NO_PAD.encode(sealed);
+
+		assert_eq!(crypter.decrypt("secret", &tampered), None);
+		assert_eq!(crypter.decrypt("other", &encrypted), None);
+		assert_eq!(crypter.decrypt("secret", "short"), None);
+		assert_eq!(crypter.decrypt("secret", "not base64!"), None);
+	}
+
+	#[test]
+	fn wrong_key_and_rotation() {
+		let old = PrivateCookies::new(OLD_KEY);
+		let encrypted = old.encrypt_value("secret", "value");
+
+		assert_eq!(PrivateCookies::new(KEY).decrypt("secret", &encrypted), None);
+
+		let rotated = PrivateCookies::new(KEY).decrypt_with(OLD_KEY);
+		let jar = parse_header(&format!("secret=garbage; secret={encrypted}"));
+		assert_eq!(rotated.get(&jar, "secret").as_deref(), Some("value"));
+
+		let reencrypted = rotated.encrypt_value("secret", "value");
+		assert_eq!(old.decrypt("secret", &reencrypted), None);
+	}
+}
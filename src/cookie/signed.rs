@@ -0,0 +1,150 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::{CookieJar, Encoding, SetCookie};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs cookie values with an HMAC so you can tell if the client changed
+/// them. The signature covers the name too, so a value can't be moved to a
+/// different cookie. The value itself is still readable by the client.
+///
+/// A signed value is the original value, a `.`, and the base64url signature.
+///
+/// For rotating keys, the newest key signs and the older ones, added with
+/// [SignedCookies::verify_with], are still accepted until you drop them.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::cookie::{parse_header, SetCookie, SignedCookies};
+///
+///let signer = SignedCookies::new(*b"a secret key that is long enough");
+///let cookie = signer.sign(SetCookie::new("theme".into(), "dark".into()));
+///
+///let jar = parse_header(&format!("theme={}", cookie.value()));
+///assert_eq!(signer.get(&jar, "theme"), Some("dark"));
+///
+///let tampered = parse_header("theme=light");
+///assert_eq!(signer.get(&tampered, "theme"), None);
+///```
+#[derive(Clone)]
+pub struct SignedCookies {
+	keys: Vec<Vec<u8>>,
+}
+
+impl SignedCookies {
+	/// The key signs new cookies. It should be random, at least 32 bytes, and
+	/// kept secret.
+	pub fn new<K: Into<Vec<u8>>>(key: K) -> Self {
+		Self {
+			keys: vec![key.into()],
+		}
+	}
+
+	/// Also accept cookies signed with this key, but don't sign with it
+	pub fn verify_with<K: Into<Vec<u8>>>(mut self, key: K) -> Self {
+		self.keys.push(key.into());
+		self
+	}
+
+	/// Replace the value of the cookie with a signed one. The value is encoded
+	/// with the cookie's [Encoding] first and it's that which is signed, so
+	/// decode what [SignedCookies::get] gives you with the same encoding.
+	pub fn sign(&self, mut cookie: SetCookie) -> SetCookie {
+		let encoded = cookie.encoding.encode(&cookie.value);
+		cookie.value = self.sign_value(&cookie.key, &encoded);
+		cookie.encoding = Encoding::Raw;
+		cookie
+	}
+
+	/// The signed version of a value for the cookie with this name
+	pub fn sign_value(&self, name: &str, value: &str) -> String {
+		let tag = Self::mac(&self.keys[0], name, value)
+			.finalize()
+			.into_bytes();
+
+		format!("{value}.{}", URL_SAFE_NO_PAD.encode(tag))
+	}
+
+	/// Check a signed value from the cookie with this name, returning the
+	/// original value if any of our keys signed it
+	pub fn verify<'a>(&self, name: &str, signed: &'a str) -> Option<&'a str> {
+		let (value, signature) = signed.rsplit_once('.')?;
+		let tag = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+		self.keys
+			.iter()
+			.any(|key| Self::mac(key, name, value).verify_slice(&tag).is_ok())
+			.then_some(value)
+	}
+
+	/// The first cookie with this name that has a good signature
+	pub fn get<'a>(&self, jar: &'a CookieJar, name: &str) -> Option<&'a str> {
+		jar.get_all(name)
+			.find_map(|signed| self.verify(name, signed))
+	}
+
+	fn mac(key: &[u8], name: &str, value: &str) -> HmacSha256 {
+		let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+		mac.update(name.as_bytes());
+		mac.update(b"=");
+		mac.update(value.as_bytes());
+		mac
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::SignedCookies;
+	use crate::cookie::{parse_header, SetCookie};
+
+	const KEY: [u8; 32] = *b"0123456789abcdef0123456789abcdef";
+
+	#[test]
+	fn tampering() {
+		let signer = SignedCookies::new(KEY);
+		let signed = signer.sign_value("flash", "saved!");
+
+		assert_eq!(signer.verify("flash", &signed), Some("saved!"));
+		assert_eq!(signer.verify("other", &signed), None);
+		assert_eq!(
+			signer.verify("flash", &signed.replacen("saved", "SAVED", 1)),
+			None
+		);
+		assert_eq!(signer.verify("flash", "saved!"), None);
+		assert_eq!(
+			SignedCookies::new(*b"wrong key").verify("flash", &signed),
+			None
+		);
+	}
+
+	#[test]
+	fn rotation() {
+		let old = SignedCookies::new(*b"old key");
+		let signed = old.sign(SetCookie::new("pref".into(), "a.b".into()));
+
+		let rotated = SignedCookies::new(KEY).verify_with(*b"old key");
+		assert_eq!(rotated.verify("pref", signed.value()), Some("a.b"));
+
+		// New cookies use the new key
+		let resigned = rotated.sign_value("pref", "a.b");
+		assert_eq!(old.verify("pref", &resigned), None);
+		assert_eq!(
+			SignedCookies::new(KEY).verify("pref", &resigned),
+			Some("a.b")
+		);
+	}
+
+	#[test]
+	fn first_valid_in_jar() {
+		let signer = SignedCookies::new(KEY);
+		let header = format!(
+			"pref=forged.AAAA; pref={}",
+			signer.sign_value("pref", "real")
+		);
+
+		assert_eq!(signer.get(&parse_header(&header), "pref"), Some("real"));
+	}
+}
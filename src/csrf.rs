@@ -0,0 +1,529 @@
+use std::{
+	convert::Infallible,
+	future::Future,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use axum::{
+	body::Body,
+	extract::FromRequestParts,
+	http::{header, request::Parts, HeaderMap, HeaderValue, Method, Request, StatusCode},
+	response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+	cookie::{parse_header, CookieJar, SameSite, SetCookie},
+	extractors::{is_urlencoded, read_body},
+	query::Query,
+	users::{random_base58, SessionId, Users},
+};
+
+/// The form field [CsrfLayer] looks for the token in
+pub const FIELD: &str = "csrf_token";
+/// The header [CsrfLayer] looks for the token in. Checked before the form.
+pub const HEADER: &str = "x-csrf-token";
+/// The cookie [CsrfLayer] gives visitors without a session, so their tokens
+/// have something to be tied to
+pub const COOKIE: &str = "csrf_id";
+
+/// Length of the random ID in the [COOKIE] cookie
+const ANONYMOUS_ID_LENGTH: usize = 24;
+
+/// How much of a form body [CsrfLayer] will read looking for the token
+const BODY_LIMIT: usize = 64 * 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Makes and checks synchronizer tokens. A token is an HMAC of the session ID,
+/// so it's tied to the session without having to store anything.
+#[derive(Clone)]
+pub struct Csrf {
+	key: Arc<[u8]>,
+}
+
+impl Csrf {
+	/// The key should be random, at least 32 bytes, and kept secret. Changing
+	/// it invalidates every token you've given out.
+	pub fn new<K: Into<Vec<u8>>>(key: K) -> Self {
+		Self {
+			key: key.into().into(),
+		}
+	}
+
+	/// The token for a session
+	pub fn token(&self, sid: &SessionId) -> String {
+		self.binding_token(&Binding::Session(sid.as_str().to_owned()))
+	}
+
+	/// Check a token given to us is the one for this session. The comparison
+	/// takes the same time however wrong the token is.
+	pub fn verify(&self, sid: &SessionId, token: &str) -> bool {
+		self.verify_binding(&Binding::Session(sid.as_str().to_owned()), token)
+	}
+
+	fn binding_token(&self, binding: &Binding) -> String {
+		URL_SAFE_NO_PAD.encode(self.mac(binding).finalize().into_bytes())
+	}
+
+	fn verify_binding(&self, binding: &Binding, token: &str) -> bool {
+		match URL_SAFE_NO_PAD.decode(token) {
+			Ok(tag) => self.mac(binding).verify_slice(&tag).is_ok(),
+			Err(_) => false,
+		}
+	}
+
+	/// Set the `csrf_token` variable of a template to the token for this session
+	#[cfg(feature = "template")]
+	pub fn inject(&self, template: &mut crate::template::Template, sid: &SessionId) {
+		template.set(FIELD, self.token(sid))
+	}
+
+	fn mac(&self, binding: &Binding) -> HmacSha256 {
+		let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+		match binding {
+			Binding::Session(sid) => mac.update(sid.as_bytes()),
+			// Kept apart from session IDs, which never have a ':'
+			Binding::Anonymous(id) => {
+				mac.update(b"anonymous:");
+				mac.update(id.as_bytes());
+			}
+		}
+		mac
+	}
+}
+
+/// How [CsrfLayer] finds the session a request belongs to, so tokens can be
+/// tied to it. Implemented for [Users] and, with the `stateless` feature, for
+/// [CookieSessions](crate::stateless::CookieSessions).
+#[async_trait]
+pub trait CsrfSessions: Send + Sync + 'static {
+	/// An ID for the request's session, if it has a valid one. It has to stay
+	/// the same for as long as the session does.
+	async fn session_id(&self, parts: &mut Parts) -> Option<String>;
+}
+
+#[async_trait]
+impl CsrfSessions for Users {
+	async fn session_id(&self, parts: &mut Parts) -> Option<String> {
+		let sid = SessionId::from_request_parts(parts, &()).await.ok()?;
+
+		self.session_by_id(sid.clone())
+			.await
+			.map(|_| sid.as_str().to_owned())
+	}
+}
+
+#[cfg(feature = "stateless")]
+#[async_trait]
+impl CsrfSessions for crate::stateless::CookieSessions {
+	async fn session_id(&self, parts: &mut Parts) -> Option<String> {
+		self.session_from_jar(&request_jar(&parts.headers))
+			.await
+			.map(|session| session.id)
+	}
+}
+
+/// A [Layer] that rejects POST, PUT, PATCH, and DELETE requests unless they
+/// carry a valid token, either in the `x-csrf-token` header or the
+/// `csrf_token` field of a urlencoded form.
+///
+/// With a session, the token is tied to it. Without one, the visitor is given
+/// a random `csrf_id` cookie and the token is tied to that instead, so forms
+/// like the login form are protected too.
+///
+/// **Only urlencoded bodies are searched for the token.** Anything else, like
+/// a `multipart/form-data` upload, is rejected unless it sends the token in
+/// the `x-csrf-token` header, which means submitting it with JavaScript.
+///
+/// It also adds the [Csrf] to the request extensions so [CsrfToken] can be
+/// extracted in the handlers that build forms.
+pub struct CsrfLayer<L = Users> {
+	csrf: Csrf,
+	sessions: Arc<L>,
+}
+
+impl<L> CsrfLayer<L> {
+	pub fn new(csrf: Csrf, sessions: Arc<L>) -> Self {
+		Self { csrf, sessions }
+	}
+}
+
+impl<L> Clone for CsrfLayer<L> {
+	fn clone(&self) -> Self {
+		Self {
+			csrf: self.csrf.clone(),
+			sessions: self.sessions.clone(),
+		}
+	}
+}
+
+impl<S, L> Layer<S> for CsrfLayer<L> {
+	type Service = CsrfService<S, L>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		CsrfService {
+			inner,
+			csrf: self.csrf.clone(),
+			sessions: self.sessions.clone(),
+		}
+	}
+}
+
+/// The [Service] made by [CsrfLayer]
+pub struct CsrfService<S, L = Users> {
+	inner: S,
+	csrf: Csrf,
+	sessions: Arc<L>,
+}
+
+impl<S: Clone, L> Clone for CsrfService<S, L> {
+	fn clone(&self) -> Self {
+		Self {
+			inner: self.inner.clone(),
+			csrf: self.csrf.clone(),
+			sessions: self.sessions.clone(),
+		}
+	}
+}
+
+impl<S, L> Service<Request<Body>> for CsrfService<S, L>
+where
+	S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+	S::Future: Send + 'static,
+	L: CsrfSessions,
+{
+	type Response = Response;
+	type Error = S::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, request: Request<Body>) -> Self::Future {
+		// The clone might not be ready, so keep the service we polled
+		let clone = self.inner.clone();
+		let mut inner = std::mem::replace(&mut self.inner, clone);
+
+		let csrf = self.csrf.clone();
+		let sessions = self.sessions.clone();
+
+		Box::pin(async move {
+			let (mut parts, body) = request.into_parts();
+
+			let mut new_anonymous = None;
+			let binding = match sessions.session_id(&mut parts).await {
+				Some(sid) => Binding::Session(sid),
+				None => {
+					let id = request_jar(&parts.headers)
+						.get(COOKIE)
+						.filter(|id| id.len() == ANONYMOUS_ID_LENGTH)
+						.map(<_>::to_owned)
+						.unwrap_or_else(|| {
+							let id = random_base58(ANONYMOUS_ID_LENGTH);
+							new_anonymous = Some(id.clone());
+							id
+						});
+
+					Binding::Anonymous(id)
+				}
+			};
+
+			parts.extensions.insert(csrf.clone());
+			parts.extensions.insert(binding.clone());
+
+			let mut response = if !unsafe_method(&parts.method) {
+				inner.call(Request::from_parts(parts, body)).await?
+			} else {
+				check(&mut inner, &csrf, &binding, parts, body).await?
+			};
+
+			if let Some(id) = new_anonymous {
+				let cookie = SetCookie::new(COOKIE.into(), id)
+					.path(Some(String::from("/")))
+					.same_site(Some(SameSite::Lax));
+
+				if let Ok(value) = HeaderValue::from_str(&cookie.as_string()) {
+					response.headers_mut().append(header::SET_COOKIE, value);
+				}
+			}
+
+			Ok(response)
+		})
+	}
+}
+
+/// Pass the request on if it has a valid token for `binding`
+async fn check<S>(
+	inner: &mut S,
+	csrf: &Csrf,
+	binding: &Binding,
+	parts: Parts,
+	body: Body,
+) -> Result<Response, S::Error>
+where
+	S: Service<Request<Body>, Response = Response>,
+{
+	if let Some(token) = header_token(&parts.headers) {
+		return if csrf.verify_binding(binding, token) {
+			inner.call(Request::from_parts(parts, body)).await
+		} else {
+			Ok(forbidden())
+		};
+	}
+
+	if !is_urlencoded(&parts.headers) {
+		return Ok(forbidden());
+	}
+
+	let bytes = match read_body(body, BODY_LIMIT).await {
+		Ok(bytes) => bytes,
+		Err(rejection) => return Ok(rejection.into_response()),
+	};
+
+	let valid = std::str::from_utf8(&bytes)
+		.ok()
+		.and_then(|body| body.parse::<Query>().ok())
+		.and_then(|form| {
+			form.get_first_value(FIELD)
+				.map(|token| csrf.verify_binding(binding, token))
+		})
+		.unwrap_or(false);
+
+	if valid {
+		inner
+			.call(Request::from_parts(parts, Body::from(bytes)))
+			.await
+	} else {
+		Ok(forbidden())
+	}
+}
+
+/// What a request's tokens are tied to. [CsrfLayer] puts it in the request
+/// extensions for [CsrfToken].
+#[derive(Clone, Debug)]
+enum Binding {
+	Session(String),
+	Anonymous(String),
+}
+
+/// The token for the current session, or for the visitor's `csrf_id` cookie
+/// if they don't have one, for putting in forms. Needs the [CsrfLayer].
+pub struct CsrfToken(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+	S: Send + Sync,
+{
+	type Rejection = Infallible;
+
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+		let csrf = parts
+			.extensions
+			.get::<Csrf>()
+			.expect("CsrfToken requires the CsrfLayer");
+		let binding = parts
+			.extensions
+			.get::<Binding>()
+			.expect("CsrfToken requires the CsrfLayer");
+
+		Ok(Self(csrf.binding_token(binding)))
+	}
+}
+
+/// Every cookie the request sent
+fn request_jar(headers: &HeaderMap) -> CookieJar {
+	headers
+		.get_all(header::COOKIE)
+		.iter()
+		.filter_map(|cookie| cookie.to_str().ok())
+		.flat_map(parse_header)
+		.collect()
+}
+
+fn unsafe_method(method: &Method) -> bool {
+	!matches!(
+		*method,
+		Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+	)
+}
+
+fn header_token(headers: &HeaderMap) -> Option<&str> {
+	headers.get(HEADER).and_then(|token| token.to_str().ok())
+}
+
+fn forbidden() -> Response {
+	(StatusCode::FORBIDDEN, "CSRF token missing or invalid").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use axum::{
+		body::Body,
+		http::{header, Request, StatusCode},
+		routing::{get, post},
+		Router,
+	};
+	use tower::ServiceExt;
+
+	use super::{Csrf, CsrfLayer, CsrfToken, COOKIE, HEADER};
+	use crate::users::{SessionId, Users};
+
+	#[test]
+	fn tokens_are_per_session() {
+		let csrf = Csrf::new(*b"0123456789abcdef0123456789abcdef");
+		let one = SessionId::from(String::from("one"));
+		let two = SessionId::from(String::from("two"));
+
+		let token = csrf.token(&one);
+		assert!(csrf.verify(&one, &token));
+		assert!(!csrf.verify(&two, &token));
+		assert!(!csrf.verify(&one, "not a token"));
+		assert!(!Csrf::new(*b"another key").verify(&one, &token));
+	}
+
+	#[tokio::test]
+	async fn layer() {
+		let users = Arc::new(Users::new());
+		let session = users
+			.register(None, "gen".into(), "password".into())
+			.await
+			.unwrap();
+		let csrf = Csrf::new(*b"0123456789abcdef0123456789abcdef");
+		let token = csrf.token(&session.sid);
+		let cookie = format!("sid={}", session.sid);
+
+		let app = Router::new()
+			.route("/", post(|body: String| async move { body }))
+			.route("/token", post(|CsrfToken(token)| async move { token }))
+			.layer(CsrfLayer::new(csrf, users));
+
+		let send = |request: Request<Body>| {
+			let app = app.clone();
+			async move { app.oneshot(request).await.unwrap() }
+		};
+		let form = |body: String| {
+			Request::post("/")
+				.header(header::COOKIE, &cookie)
+				.header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+				.body(Body::from(body))
+				.unwrap()
+		};
+
+		let response = send(form(String::from("name=gen"))).await;
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+		let response = send(form(format!("name=gen&csrf_token={token}"))).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, format!("name=gen&csrf_token={token}"));
+
+		let request = Request::post("/token")
+			.header(header::COOKIE, &cookie)
+			.header(HEADER, &token)
+			.body(Body::empty())
+			.unwrap();
+		let response = send(request).await;
+		assert_eq!(response.status(), StatusCode::OK);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert_eq!(body, token);
+
+		let request = Request::post("/")
+			.header(header::CONTENT_TYPE, "multipart/form-data; boundary=x")
+			.header(header::COOKIE, &cookie)
+			.body(Body::from("--x--"))
+			.unwrap();
+		assert_eq!(send(request).await.status(), StatusCode::FORBIDDEN);
+	}
+
+	/// Forging a login is an attack too, so visitors without a session need a
+	/// token as well
+	#[tokio::test]
+	async fn anonymous() {
+		let csrf = Csrf::new(*b"0123456789abcdef0123456789abcdef");
+		let app = Router::new()
+			.route(
+				"/login",
+				get(|CsrfToken(token)| async move { token }).post(|| async { "welcome" }),
+			)
+			.layer(CsrfLayer::new(csrf, Arc::new(Users::new())));
+
+		let request = Request::post("/login").body(Body::empty()).unwrap();
+		let response = app.clone().oneshot(request).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+		let request = Request::get("/login").body(Body::empty()).unwrap();
+		let response = app.clone().oneshot(request).await.unwrap();
+		let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+		assert!(set_cookie.starts_with(&format!("{COOKIE}=")));
+		let cookie = set_cookie.split(';').next().unwrap().to_owned();
+		let token = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		let token = std::str::from_utf8(&token).unwrap();
+
+		let login = |cookie: &str, token: &str| {
+			Request::post("/login")
+				.header(header::COOKIE, cookie)
+				.header(HEADER, token)
+				.body(Body::empty())
+				.unwrap()
+		};
+
+		let response = app.clone().oneshot(login(&cookie, token)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::OK);
+		assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+		let other = format!("{COOKIE}={}", "a".repeat(24));
+		let response = app.oneshot(login(&other, token)).await.unwrap();
+		assert_eq!(response.status(), StatusCode::FORBIDDEN);
+	}
+
+	#[cfg(feature = "stateless")]
+	#[tokio::test]
+	async fn cookie_sessions() {
+		use crate::{cookie::SignedCookies, stateless::CookieSessions, users::UserId};
+
+		let sessions = Arc::new(CookieSessions::signed(SignedCookies::new(
+			*b"0123456789abcdef0123456789abcdef",
+		)));
+		let session = sessions.new_session(UserId::from(String::from("abc123")));
+		let set_cookie = sessions.login_cookie(&session).unwrap();
+		let cookie = set_cookie.split(';').next().unwrap();
+
+		let app = Router::new()
+			.route(
+				"/",
+				get(|CsrfToken(token)| async move { token }).post(|| async { "ok" }),
+			)
+			.layer(CsrfLayer::new(
+				Csrf::new(*b"0123456789abcdef0123456789abcdef"),
+				sessions,
+			));
+
+		let request = Request::get("/")
+			.header(header::COOKIE, cookie)
+			.body(Body::empty())
+			.unwrap();
+		let response = app.clone().oneshot(request).await.unwrap();
+		// A session means no csrf_id cookie
+		assert!(response.headers().get(header::SET_COOKIE).is_none());
+		let token = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+		let request = Request::post("/")
+			.header(header::COOKIE, cookie)
+			.header(HEADER, std::str::from_utf8(&token).unwrap())
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+	}
+}
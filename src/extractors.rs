@@ -1,15 +1,21 @@
-use std::sync::Arc;
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use axum::{
-	extract::FromRequestParts,
-	http::request::Parts,
-	response::{IntoResponse, Response},
+	body::{Body, Bytes, HttpBody},
+	extract::{FromRequest, FromRequestParts},
+	http::{header, request::Parts, HeaderMap, HeaderValue, Request, StatusCode},
+	response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
 	Extension, RequestPartsExt,
 };
-use hyper::header;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
 
-use crate::users::{SessionId, Users};
+#[cfg(feature = "cookie")]
+use crate::cookie::{CookieJar, SetCookie};
+use crate::query::{DecodeMode, Query, QueryParseError, QuerySerdeError};
+#[cfg(all(feature = "users", feature = "cookie"))]
+use crate::users::{Session, SessionId, Users};
 
 #[cfg(all(feature = "users", feature = "cookie"))]
 #[async_trait]
@@ -27,8 +33,7 @@ where
 				.ok_or(())?
 				.to_str()
 				.map_err(|_| ())?,
-		)
-		.map_err(|_| ())?;
+		);
 
 		Ok(Self(cookie.get("sid").ok_or(())?.to_string()))
 	}
@@ -56,3 +61,629 @@ where
 		}
 	}
 }
+
+/// Mirrors the [Session](crate::users::Session) extractor for sessions kept in
+/// a cookie. Needs an `Extension<Arc<CookieSessions>>` layer. Rejects with a
+/// 401 if there's no valid session.
+#[cfg(feature = "stateless")]
+#[async_trait]
+impl<S> FromRequestParts<S> for crate::stateless::CookieSession
+where
+	S: Send + Sync,
+{
+	type Rejection = Response;
+
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+		let sessions = parts
+			.extensions
+			.get::<Arc<crate::stateless::CookieSessions>>()
+			.cloned()
+			.expect("CookieSession requires an Extension<Arc<CookieSessions>> layer");
+
+		// HTTP/2 clients can split cookies over more than one header
+		let jar = parts
+			.headers
+			.get_all(header::COOKIE)
+			.iter()
+			.filter_map(|cookie| cookie.to_str().ok())
+			.flat_map(crate::cookie::parse_header)
+			.collect();
+
+		sessions
+			.session_from_jar(&jar)
+			.await
+			.ok_or_else(|| StatusCode::UNAUTHORIZED.into_response())
+	}
+}
+
+/// A [Session] for pages that work both logged in and logged out. Extracting
+/// it never fails: a missing `sid` cookie, or one that doesn't belong to a
+/// session, leaves `session` as `None`.
+///
+/// When the client sent a `sid` that is no longer valid, [MaybeSession::clear_cookie]
+/// returns a Set-Cookie value that removes it. Returning the `MaybeSession` as
+/// part of a response adds that header for you.
+///
+/// Like [Session], this needs an `Extension<Arc<Users>>` layer.
+#[cfg(all(feature = "users", feature = "cookie"))]
+pub struct MaybeSession {
+	pub session: Option<Session>,
+	stale: bool,
+}
+
+#[cfg(all(feature = "users", feature = "cookie"))]
+impl MaybeSession {
+	/// The value of a Set-Cookie header clearing the `sid` cookie, if the client
+	/// presented one that didn't match a session.
+	pub fn clear_cookie(&self) -> Option<String> {
+		self.stale.then(crate::users::session_removal_cookie)
+	}
+
+	pub fn into_session(self) -> Option<Session> {
+		self.session
+	}
+}
+
+#[cfg(all(feature = "users", feature = "cookie"))]
+#[async_trait]
+impl<S> FromRequestParts<S> for MaybeSession
+where
+	S: Send + Sync,
+{
+	type Rejection = Infallible;
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let sid = match SessionId::from_request_parts(parts, state).await {
+			Ok(sid) => sid,
+			Err(()) => {
+				return Ok(Self {
+					session: None,
+					stale: false,
+				})
+			}
+		};
+
+		let users = parts
+			.extensions
+			.get::<Arc<Users>>()
+			.expect("MaybeSession requires an Extension<Arc<Users>> layer");
+
+		let session = users.session_by_id(sid).await;
+
+		Ok(Self {
+			stale: session.is_none(),
+			session,
+		})
+	}
+}
+
+#[cfg(all(feature = "users", feature = "cookie"))]
+impl IntoResponseParts for MaybeSession {
+	type Error = Infallible;
+
+	fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+		if let Some(cookie) = self.clear_cookie() {
+			if let Ok(value) = HeaderValue::from_str(&cookie) {
+				res.headers_mut().append(header::SET_COOKIE, value);
+			}
+		}
+
+		Ok(res)
+	}
+}
+
+/// The request's cookies, and the ones to send back. Take it as `mut`, change
+/// it with [Cookies::add] and [Cookies::remove], and return it as part of the
+/// response to write a Set-Cookie header for each change.
+///
+///```rust
+///use mavourings::{cookie::SetCookie, extractors::Cookies};
+///
+///async fn visit(mut cookies: Cookies) -> (Cookies, String) {
+///    let visits = cookies.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0u32);
+///    cookies.add(SetCookie::new("visits".into(), (visits + 1).to_string()));
+///
+///    (cookies, format!("you've been here {visits} times before"))
+///}
+///```
+#[cfg(feature = "cookie")]
+#[derive(Clone, Debug, Default)]
+pub struct Cookies {
+	jar: CookieJar,
+	decoded: CookieJar,
+	changes: Vec<SetCookie>,
+}
+
+#[cfg(feature = "cookie")]
+impl Cookies {
+	pub fn new(jar: CookieJar) -> Self {
+		let decoded = jar
+			.iter()
+			.map(|(name, value)| (name.to_owned(), percent_decode(value)))
+			.collect();
+
+		Self {
+			jar,
+			decoded,
+			changes: vec![],
+		}
+	}
+
+	/// The value of a cookie, taking changes made with [Cookies::add] and
+	/// [Cookies::remove] into account.
+	///
+	/// Values from the request are percent-decoded to undo the default
+	/// [Encoding::Percent](crate::cookie::Encoding::Percent), so you get back
+	/// what you set. For cookies set with another encoding, or signed or
+	/// private cookies, use the raw values from [Cookies::jar].
+	pub fn get<S: AsRef<str>>(&self, name: S) -> Option<&str> {
+		let name = name.as_ref();
+
+		match self.changes.iter().rev().find(|c| c.key() == name) {
+			Some(change) if change.get_max_age() == Some(Duration::ZERO) => None,
+			Some(change) => Some(change.value()),
+			None => self.decoded.get(name),
+		}
+	}
+
+	/// The cookies as the client sent them, not decoded
+	pub fn jar(&self) -> &CookieJar {
+		&self.jar
+	}
+
+	/// Set a cookie. Replaces an earlier change to the cookie with the same
+	/// name, path, and domain.
+	pub fn add(&mut self, cookie: SetCookie) {
+		self.changes.retain(|change| !same_cookie(change, &cookie));
+		self.changes.push(cookie);
+	}
+
+	/// Tell the client to delete a cookie that was set with `Path=/`
+	pub fn remove<S: Into<String>>(&mut self, name: S) {
+		self.add(SetCookie::removal(name).path(Some(String::from("/"))))
+	}
+
+	/// The cookies that will be sent with the response
+	pub fn changes(&self) -> &[SetCookie] {
+		&self.changes
+	}
+}
+
+/// Cookies come from the client, so a bad escape is left as it is rather than
+/// failing the whole extractor
+#[cfg(feature = "cookie")]
+fn percent_decode(value: &str) -> String {
+	Query::url_decode_with(value, false, DecodeMode::Lossy).unwrap_or_else(|_| value.to_owned())
+}
+
+#[cfg(feature = "cookie")]
+fn same_cookie(a: &SetCookie, b: &SetCookie) -> bool {
+	a.key() == b.key() && a.get_path() == b.get_path() && a.get_domain() == b.get_domain()
+}
+
+#[cfg(feature = "cookie")]
+#[async_trait]
+impl<S> FromRequestParts<S> for Cookies
+where
+	S: Send + Sync,
+{
+	type Rejection = Infallible;
+
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+		let jar = parts
+			.headers
+			.get_all(header::COOKIE)
+			.iter()
+			.filter_map(|cookie| cookie.to_str().ok())
+			.flat_map(crate::cookie::parse_header)
+			.collect();
+
+		Ok(Self::new(jar))
+	}
+}
+
+/// Fails with a 500 if one of the cookies isn't valid, rather than sending a
+/// header the browser would ignore.
+#[cfg(feature = "cookie")]
+impl IntoResponseParts for Cookies {
+	type Error = (StatusCode, String);
+
+	fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+		for cookie in self.changes {
+			let value = cookie
+				.try_as_string()
+				.map_err(|e| e.to_string())
+				.and_then(|cookie| HeaderValue::from_str(&cookie).map_err(|e| e.to_string()))
+				.map_err(|e| {
+					(
+						StatusCode::INTERNAL_SERVER_ERROR,
+						format!("bad cookie {}: {e}", cookie.key()),
+					)
+				})?;
+
+			res.headers_mut().append(header::SET_COOKIE, value);
+		}
+
+		Ok(res)
+	}
+}
+
+/// Why a [Query] or [TypedQuery] couldn't be extracted. Responds with a 400
+/// that says which parameter was wrong.
+#[derive(Debug, Error)]
+pub enum QueryRejection {
+	#[error("the query string is invalid: {0}")]
+	Parse(#[from] QueryParseError),
+	#[error("bad query parameter {}", .0)]
+	Deserialize(#[from] QuerySerdeError),
+}
+
+impl IntoResponse for QueryRejection {
+	fn into_response(self) -> Response {
+		(StatusCode::BAD_REQUEST, self.to_string()).into_response()
+	}
+}
+
+/// The query string of the request's URI. A request without one gets an
+/// empty [Query].
+#[async_trait]
+impl<S> FromRequestParts<S> for Query
+where
+	S: Send + Sync,
+{
+	type Rejection = QueryRejection;
+
+	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+		Ok(parts.uri.query().unwrap_or_default().parse()?)
+	}
+}
+
+/// The query string deserialized into `T` with [query::from_query](crate::query::from_query).
+///
+///```rust
+///use mavourings::extractors::TypedQuery;
+///use serde::Deserialize;
+///
+///#[derive(Deserialize)]
+///struct Page {
+///    page: Option<u32>,
+///}
+///
+///async fn list(TypedQuery(page): TypedQuery<Page>) -> String {
+///    format!("page {}", page.page.unwrap_or(1))
+///}
+///```
+#[derive(Clone, Debug)]
+pub struct TypedQuery<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for TypedQuery<T>
+where
+	S: Send + Sync,
+	T: DeserializeOwned,
+{
+	type Rejection = QueryRejection;
+
+	async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+		let query = Query::from_request_parts(parts, state).await?;
+		Ok(Self(crate::query::from_query(&query)?))
+	}
+}
+
+/// How much of a form body [FormBody] and [TypedForm] read if there's no
+/// [FormLimit]
+pub const DEFAULT_FORM_LIMIT: usize = 64 * 1024;
+
+/// Add this as an extension to change how big a form body can be
+#[derive(Clone, Copy, Debug)]
+pub struct FormLimit(pub usize);
+
+impl Default for FormLimit {
+	fn default() -> Self {
+		Self(DEFAULT_FORM_LIMIT)
+	}
+}
+
+/// Why a [FormBody] or [TypedForm] couldn't be extracted
+#[derive(Debug, Error)]
+pub enum FormRejection {
+	#[error("expected an application/x-www-form-urlencoded body")]
+	UnsupportedMediaType,
+	#[error("the body is longer than {0} bytes")]
+	TooLarge(usize),
+	#[error("the body could not be read")]
+	ReadFailed,
+	#[error("the form is invalid: {0}")]
+	Parse(#[from] QueryParseError),
+	#[error("bad form field {0}")]
+	Deserialize(#[from] QuerySerdeError),
+}
+
+impl IntoResponse for FormRejection {
+	fn into_response(self) -> Response {
+		let status = match self {
+			FormRejection::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+			FormRejection::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+			_ => StatusCode::BAD_REQUEST,
+		};
+
+		(status, self.to_string()).into_response()
+	}
+}
+
+/// An `application/x-www-form-urlencoded` body, parsed like a query string.
+/// It has to fit in the [FormLimit].
+pub struct FormBody(pub Query);
+
+#[async_trait]
+impl<S> FromRequest<S, Body> for FormBody
+where
+	S: Send + Sync,
+{
+	type Rejection = FormRejection;
+
+	async fn from_request(request: Request<Body>, _state: &S) -> Result<Self, Self::Rejection> {
+		if !is_urlencoded(request.headers()) {
+			return Err(FormRejection::UnsupportedMediaType);
+		}
+
+		let FormLimit(limit) = request
+			.extensions()
+			.get::<FormLimit>()
+			.copied()
+			.unwrap_or_default();
+
+		let bytes = read_body(request.into_body(), limit).await?;
+		let body = std::str::from_utf8(&bytes).map_err(|_| QueryParseError::InvalidUtf8)?;
+
+		Ok(Self(body.parse()?))
+	}
+}
+
+/// A form body deserialized into `T`, see [FormBody] and
+/// [query::from_query](crate::query::from_query).
+///
+///```rust
+///use mavourings::extractors::TypedForm;
+///use serde::Deserialize;
+///
+///#[derive(Deserialize)]
+///struct Comment {
+///    body: String,
+///    #[serde(default)]
+///    notify: bool,
+///}
+///
+///async fn comment(TypedForm(comment): TypedForm<Comment>) -> String {
+///    comment.body
+///}
+///```
+#[derive(Clone, Debug)]
+pub struct TypedForm<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S, Body> for TypedForm<T>
+where
+	S: Send + Sync,
+	T: DeserializeOwned,
+{
+	type Rejection = FormRejection;
+
+	async fn from_request(request: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+		let FormBody(form) = FormBody::from_request(request, state).await?;
+		Ok(Self(crate::query::from_query(&form)?))
+	}
+}
+
+pub(crate) fn is_urlencoded(headers: &HeaderMap) -> bool {
+	headers
+		.get(header::CONTENT_TYPE)
+		.and_then(|ctype| ctype.to_str().ok())
+		.map(|ctype| ctype.starts_with("application/x-www-form-urlencoded"))
+		.unwrap_or(false)
+}
+
+/// Read a whole body, giving up if it's longer than `limit`
+pub(crate) async fn read_body(mut body: Body, limit: usize) -> Result<Bytes, FormRejection> {
+	let mut buf = Vec::new();
+
+	while let Some(chunk) = body.data().await {
+		let chunk = chunk.map_err(|_| FormRejection::ReadFailed)?;
+
+		if buf.len() + chunk.len() > limit {
+			return Err(FormRejection::TooLarge(limit));
+		}
+		buf.extend_from_slice(&chunk);
+	}
+
+	Ok(buf.into())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use axum::{
+		body::Body,
+		extract::{FromRequest, FromRequestParts},
+		http::{header, Request},
+		response::IntoResponse,
+	};
+
+	use super::{Cookies, FormLimit, FormRejection, MaybeSession, TypedForm, TypedQuery};
+	use crate::{cookie::SetCookie, users::Users};
+
+	async fn extract(users: &Arc<Users>, cookie: Option<&str>) -> MaybeSession {
+		let mut request = Request::builder().uri("/");
+		if let Some(cookie) = cookie {
+			request = request.header(header::COOKIE, cookie);
+		}
+
+		let (mut parts, _) = request.body(()).unwrap().into_parts();
+		parts.extensions.insert(users.clone());
+
+		MaybeSession::from_request_parts(&mut parts, &())
+			.await
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn maybe_session() {
+		let users = Arc::new(Users::new());
+		let session = users
+			.register(None, "gen".into(), "password".into())
+			.await
+			.unwrap();
+
+		let guest = extract(&users, None).await;
+		assert!(guest.session.is_none());
+		assert!(guest.clear_cookie().is_none());
+
+		let cookie = format!("sid={}", session.sid);
+		let logged_in = extract(&users, Some(&cookie)).await;
+		assert_eq!(logged_in.session.unwrap().stub.username, "gen");
+
+		let stale = extract(&users, Some("sid=notasession")).await;
+		assert!(stale.session.is_none());
+		assert!(stale
+			.clear_cookie()
+			.unwrap()
+			.starts_with("sid=; Expires=Thu, 01 Jan 1970"));
+	}
+
+	#[tokio::test]
+	#[cfg(feature = "stateless")]
+	async fn cookie_session_split_headers() {
+		use crate::{
+			cookie::SignedCookies,
+			id::UserId,
+			stateless::{CookieSession, CookieSessions},
+		};
+
+		let sessions = Arc::new(CookieSessions::signed(SignedCookies::new(*b"key")));
+		let session = sessions.new_session(UserId::from(String::from("abc123")));
+		let set_cookie = sessions.login_cookie(&session).unwrap();
+
+		let (mut parts, _) = Request::builder()
+			.header(header::COOKIE, "theme=dark")
+			.header(header::COOKIE, set_cookie.split(';').next().unwrap())
+			.body(())
+			.unwrap()
+			.into_parts();
+		parts.extensions.insert(sessions);
+
+		let extracted = CookieSession::from_request_parts(&mut parts, &()).await;
+		assert_eq!(extracted.ok(), Some(session));
+	}
+
+	#[tokio::test]
+	async fn cookies() {
+		let (mut parts, _) = Request::builder()
+			.header(header::COOKIE, "theme=dark; sid=abc")
+			.header(header::COOKIE, "lang=en")
+			.body(())
+			.unwrap()
+			.into_parts();
+
+		let mut cookies = Cookies::from_request_parts(&mut parts, &()).await.unwrap();
+		assert_eq!(cookies.get("lang"), Some("en"));
+
+		cookies.add(SetCookie::new("theme".into(), "light".into()));
+		cookies.add(SetCookie::new("theme".into(), "blue".into()));
+		cookies.remove("sid");
+		assert_eq!(cookies.get("theme"), Some("blue"));
+		assert_eq!(cookies.get("sid"), None);
+		assert_eq!(cookies.jar().get("sid"), Some("abc"));
+
+		let response = (cookies, "ok").into_response();
+		let set: Vec<_> = response
+			.headers()
+			.get_all(header::SET_COOKIE)
+			.iter()
+			.map(|v| v.to_str().unwrap())
+			.collect();
+		assert_eq!(set.len(), 2);
+		assert!(set[0].starts_with("theme=blue;"));
+		assert!(set[1].starts_with("sid=;"));
+
+		let mut bad = Cookies::default();
+		bad.add(SetCookie::new("__Host-id".into(), "1".into()));
+		let response = (bad, "ok").into_response();
+		assert_eq!(response.status(), 500);
+	}
+
+	#[tokio::test]
+	async fn cookies_round_trip() {
+		let mut cookies = Cookies::default();
+		cookies.add(SetCookie::new(
+			"greeting".into(),
+			"hello, wörld; 100%".into(),
+		));
+		let response = (cookies, "ok").into_response();
+		let set = response.headers()[header::SET_COOKIE].to_str().unwrap();
+		let pair = set.split(';').next().unwrap();
+
+		let (mut parts, _) = Request::builder()
+			.header(header::COOKIE, pair)
+			.body(())
+			.unwrap()
+			.into_parts();
+		let cookies = Cookies::from_request_parts(&mut parts, &()).await.unwrap();
+
+		assert_eq!(cookies.get("greeting"), Some("hello, wörld; 100%"));
+		assert_ne!(cookies.jar().get("greeting"), cookies.get("greeting"));
+	}
+
+	#[tokio::test]
+	async fn typed_query() {
+		#[derive(Debug, serde::Deserialize)]
+		struct Page {
+			page: u32,
+		}
+
+		let extract = |uri: &'static str| async move {
+			let (mut parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+			TypedQuery::<Page>::from_request_parts(&mut parts, &()).await
+		};
+
+		assert_eq!(extract("/?page=2").await.unwrap().0.page, 2);
+
+		let response = extract("/?page=two").await.unwrap_err().into_response();
+		assert_eq!(response.status(), 400);
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		assert!(body.starts_with(b"bad query parameter page: "));
+	}
+
+	#[tokio::test]
+	async fn typed_form() {
+		#[derive(Debug, serde::Deserialize)]
+		struct Comment {
+			body: String,
+		}
+
+		let extract = |ctype: &'static str, body: &'static str| async move {
+			let request = Request::post("/")
+				.header(header::CONTENT_TYPE, ctype)
+				.extension(FormLimit(16))
+				.body(Body::from(body))
+				.unwrap();
+			TypedForm::<Comment>::from_request(request, &()).await
+		};
+
+		let form = "application/x-www-form-urlencoded";
+		assert_eq!(
+			extract(form, "body=hi+there").await.unwrap().0.body,
+			"hi there"
+		);
+
+		let status = |result: Result<TypedForm<Comment>, FormRejection>| {
+			result.unwrap_err().into_response().status()
+		};
+		assert_eq!(status(extract("text/plain", "body=hi").await), 415);
+		assert_eq!(
+			status(extract(form, "body=far+too+long+for+this").await),
+			413
+		);
+		assert_eq!(status(extract(form, "comment=hi").await), 400);
+	}
+}
@@ -1,13 +1,29 @@
 #[cfg(feature = "cookie")]
 pub mod cookie;
 
+#[cfg(any(feature = "users", feature = "stateless"))]
+pub mod id;
+
 #[cfg(feature = "users")]
 pub mod users;
 
 #[cfg(feature = "extractors")]
 pub mod extractors;
 
+#[cfg(feature = "auth")]
+pub mod auth;
+
+#[cfg(feature = "csrf")]
+pub mod csrf;
+
+#[cfg(feature = "oauth")]
+pub mod oauth;
+
+#[cfg(feature = "stateless")]
+pub mod stateless;
+
 pub mod query;
+pub mod url;
 
 #[cfg(feature = "send_file")]
 pub async fn file_string_reply<P: AsRef<std::path::Path>>(
@@ -49,6 +65,96 @@ pub async fn file_reply<P: AsRef<std::path::Path>>(
 		.map_err(|e| Box::new(e) as Box<dyn Error>)
 }
 
+/// Like [file_reply], but the file is read in chunks as the body is sent
+/// instead of all at once, so large files don't have to fit in memory. The
+/// Content-Length comes from the file's metadata.
+#[cfg(feature = "send_file")]
+pub async fn file_stream_reply<P: AsRef<std::path::Path>>(
+	path: P,
+) -> Result<hyper::Response<hyper::Body>, Box<dyn std::error::Error>> {
+	use hyper::{Body, Response};
+	use mime_guess::MimeGuess;
+	use std::error::Error;
+
+	let file = tokio::fs::File::open(path.as_ref()).await?;
+	let metadata = file.metadata().await?;
+
+	// Opening a directory works, but reading it doesn't
+	if !metadata.is_file() {
+		return Err(Box::new(std::io::Error::new(
+			std::io::ErrorKind::InvalidInput,
+			"not a file",
+		)));
+	}
+
+	let length = metadata.len();
+
+	let mut resp = Response::builder()
+		.status(200)
+		.header("content-length", length);
+
+	if let Some(guess) = MimeGuess::from_path(path).first() {
+		resp = resp.header("content-type", guess.to_string());
+	};
+
+	resp.body(Body::wrap_stream(FileStream::new(file)))
+		.map_err(|e| Box::new(e) as Box<dyn Error>)
+}
+
+/// How much of a file [FileStream] reads at a time
+#[cfg(feature = "send_file")]
+pub const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A file read as a stream of [FILE_CHUNK_SIZE] chunks, for use with
+/// `hyper::Body::wrap_stream`. This is what [file_stream_reply] sends.
+#[cfg(feature = "send_file")]
+pub struct FileStream {
+	file: tokio::fs::File,
+	buffer: bytes::BytesMut,
+}
+
+#[cfg(feature = "send_file")]
+impl FileStream {
+	pub fn new(file: tokio::fs::File) -> Self {
+		Self {
+			file,
+			buffer: bytes::BytesMut::new(),
+		}
+	}
+}
+
+#[cfg(feature = "send_file")]
+impl futures_core::Stream for FileStream {
+	type Item = Result<bytes::Bytes, std::io::Error>;
+
+	fn poll_next(
+		self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> std::task::Poll<Option<Self::Item>> {
+		use std::{pin::Pin, task::Poll};
+		use tokio::io::{AsyncRead, ReadBuf};
+
+		let this = self.get_mut();
+		// Gets the allocation back if hyper is done with the last chunk
+		this.buffer.resize(FILE_CHUNK_SIZE, 0);
+		let mut buf = ReadBuf::new(&mut this.buffer);
+
+		let read = match Pin::new(&mut this.file).poll_read(cx, &mut buf) {
+			Poll::Pending => return Poll::Pending,
+			Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+			Poll::Ready(Ok(())) => buf.filled().len(),
+		};
+
+		// Nothing read means we're at the end of the file
+		if read == 0 {
+			return Poll::Ready(None);
+		}
+
+		this.buffer.truncate(read);
+		Poll::Ready(Some(Ok(this.buffer.split().freeze())))
+	}
+}
+
 #[cfg(feature = "template")]
 pub mod template {
 	use core::fmt;
@@ -117,3 +223,35 @@ pub mod template {
 		}
 	}
 }
+
+#[cfg(all(test, feature = "send_file"))]
+mod tests {
+	use super::{file_stream_reply, FILE_CHUNK_SIZE};
+
+	#[tokio::test]
+	async fn streams_whole_file() {
+		let path = std::env::temp_dir().join(format!("mavourings-{}.txt", std::process::id()));
+		// A few chunks and a bit, so the last one is short
+		let contents: Vec<u8> = (0..FILE_CHUNK_SIZE * 3 + 17)
+			.map(|i| (i % 251) as u8)
+			.collect();
+		tokio::fs::write(&path, &contents).await.unwrap();
+
+		let response = file_stream_reply(&path).await.unwrap();
+		assert_eq!(
+			response.headers()["content-length"],
+			contents.len().to_string()
+		);
+		assert_eq!(response.headers()["content-type"], "text/plain");
+
+		let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+		tokio::fs::remove_file(&path).await.unwrap();
+
+		assert_eq!(body, contents);
+	}
+
+	#[tokio::test]
+	async fn rejects_directories() {
+		assert!(file_stream_reply(std::env::temp_dir()).await.is_err());
+	}
+}
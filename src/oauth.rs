@@ -0,0 +1,695 @@
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+	cookie::{SameSite, SetCookie},
+	query::Query,
+	users::{random_base58, ExternalId, Session, Users},
+};
+
+/// How long someone has to finish logging in at the provider
+const PENDING_LIFETIME: Duration = Duration::from_secs(10 * 60);
+/// How many logins can be in progress at once by default, see
+/// [Client::max_pending]
+const MAX_PENDING: usize = 4096;
+/// Clock skew we allow when checking an ID token's expiry and issue time
+const LEEWAY: u64 = 60;
+
+/// The cookie [Authorization::state_cookie] keeps the state in
+pub const STATE_COOKIE: &str = "oauth_state";
+
+/// An OpenID Connect provider and how we're registered with it
+#[derive(Clone, Debug)]
+pub struct Provider {
+	/// Must exactly match the `iss` of the ID tokens it gives out
+	pub issuer: String,
+	pub authorization_endpoint: String,
+	pub token_endpoint: String,
+	pub client_id: String,
+	/// Sent in the token request body if present
+	pub client_secret: Option<String>,
+	pub redirect_uri: String,
+	/// Always includes `openid`
+	pub scopes: Vec<String>,
+}
+
+/// Makes the token request to the provider. This is left to you so you can use
+/// whatever HTTP client you already have, or a mock one in tests.
+#[async_trait]
+pub trait Transport: Send + Sync {
+	/// POST `body` as `application/x-www-form-urlencoded` to `url` and return
+	/// the response body. A non-success status should be an `Err`
+	async fn post_form(&self, url: &str, body: String) -> Result<Vec<u8>, String>;
+}
+
+/// Where to send the user to log in. Redirect them to `url`, setting
+/// [Authorization::state_cookie] on the same response.
+#[derive(Clone, Debug)]
+pub struct Authorization {
+	pub url: String,
+	pub state: String,
+}
+
+impl Authorization {
+	/// The value bit of a Set-Cookie header that keeps the state in the browser
+	/// starting the login. Its value is what [Client::callback] expects, so a
+	/// state and code someone else got can't be used to log this browser in
+	/// as them.
+	///
+	/// It's SameSite=Lax, not Strict, because the provider's redirect back is
+	/// from another site.
+	pub fn state_cookie(&self) -> String {
+		SetCookie::new(String::from(STATE_COOKIE), self.state.clone())
+			.max_age(Some(PENDING_LIFETIME))
+			.same_site(Some(SameSite::Lax))
+			.path(Some(String::from("/")))
+			.as_string()
+	}
+}
+
+/// The value bit of a Set-Cookie header that clears [STATE_COOKIE] once the
+/// login's finished
+pub fn state_removal_cookie() -> String {
+	SetCookie::removal(STATE_COOKIE)
+		.path(Some(String::from("/")))
+		.as_string()
+}
+
+/// The claims from an ID token that we check or that are useful for making
+/// a user.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Claims {
+	pub iss: String,
+	pub sub: String,
+	pub aud: Audience,
+	pub azp: Option<String>,
+	pub exp: u64,
+	pub iat: u64,
+	pub nonce: Option<String>,
+	pub email: Option<String>,
+	pub preferred_username: Option<String>,
+}
+
+impl Claims {
+	/// The issuer and subject of the user, for linking to a [UserEntry](crate::users::UserEntry)
+	pub fn external_id(&self) -> ExternalId {
+		ExternalId::new(&self.iss, &self.sub)
+	}
+}
+
+/// The `aud` claim can be a single string or a list of them
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+	One(String),
+	Many(Vec<String>),
+}
+
+impl Audience {
+	pub fn contains(&self, client_id: &str) -> bool {
+		match self {
+			Audience::One(aud) => aud == client_id,
+			Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+		}
+	}
+
+	fn len(&self) -> usize {
+		match self {
+			Audience::One(_) => 1,
+			Audience::Many(auds) => auds.len(),
+		}
+	}
+}
+
+/// A completed login at the provider
+#[derive(Clone, Debug)]
+pub struct Authenticated {
+	pub claims: Claims,
+	pub access_token: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+	access_token: String,
+	id_token: Option<String>,
+}
+
+struct Pending {
+	nonce: String,
+	verifier: String,
+	started: Instant,
+}
+
+/// An authorization code flow client using PKCE, state, and nonce.
+///
+/// The ID token comes straight from the token endpoint, so we trust the
+/// transport's TLS for where it came from and only check its claims, as
+/// OpenID Connect Core 3.1.3.7 allows. Its signature isn't verified.
+pub struct Client<T: Transport> {
+	provider: Provider,
+	transport: T,
+	pending: Mutex<HashMap<String, Pending>>,
+	max_pending: usize,
+}
+
+impl<T: Transport> Client<T> {
+	pub fn new(provider: Provider, transport: T) -> Self {
+		Self {
+			provider,
+			transport,
+			pending: Mutex::new(HashMap::new()),
+			max_pending: MAX_PENDING,
+		}
+	}
+
+	/// How many logins can be started and not finished at once. Anyone can
+	/// start one, so past this the oldest is forgotten to keep memory bounded.
+	/// Defaults to 4096.
+	pub fn max_pending(mut self, max_pending: usize) -> Self {
+		self.max_pending = max_pending.max(1);
+		self
+	}
+
+	pub fn provider(&self) -> &Provider {
+		&self.provider
+	}
+
+	/// Start a login. Send the user to the returned URL with its
+	/// [state cookie](Authorization::state_cookie); the provider will send
+	/// them back to the redirect URI where you call [Client::callback].
+	pub fn authorize(&self) -> Authorization {
+		let state = random_base58(32);
+		let nonce = random_base58(32);
+		// Base58 is all unreserved characters so it's a fine verifier
+		let verifier = random_base58(64);
+		let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+		let mut scopes = vec!["openid"];
+		scopes.extend(
+			self.provider
+				.scopes
+				.iter()
+				.map(String::as_str)
+				.filter(|scope| *scope != "openid"),
+		);
+
+		let separator = if self.provider.authorization_endpoint.contains('?') {
+			'&'
+		} else {
+			'?'
+		};
+
+		let url = format!(
+			"{}{separator}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+			self.provider.authorization_endpoint,
+			Query::url_encode(&self.provider.client_id),
+			Query::url_encode(&self.provider.redirect_uri),
+			Query::url_encode(scopes.join(" ")),
+			state,
+			nonce,
+			challenge
+		);
+
+		let mut pending = self.pending.lock().unwrap();
+		pending.retain(|_, pending| pending.started.elapsed() < PENDING_LIFETIME);
+		while pending.len() >= self.max_pending {
+			let oldest = pending
+				.iter()
+				.min_by_key(|(_, pending)| pending.started)
+				.map(|(state, _)| state.clone());
+
+			match oldest {
+				Some(state) => pending.remove(&state),
+				None => break,
+			};
+		}
+		pending.insert(
+			state.clone(),
+			Pending {
+				nonce,
+				verifier,
+				started: Instant::now(),
+			},
+		);
+
+		Authorization { url, state }
+	}
+
+	/// Finish a login with the query the provider redirected back with.
+	/// `expected_state` is the value of the [STATE_COOKIE] the browser sent,
+	/// if it sent one. Checks the state, trades the code for tokens, and
+	/// checks the ID token.
+	pub async fn callback(
+		&self,
+		query: &Query,
+		expected_state: Option<&str>,
+	) -> Result<Authenticated, Error> {
+		if let Some(error) = query.get_first_value("error") {
+			return Err(Error::Provider {
+				error: error.to_owned(),
+				description: query
+					.get_first_value("error_description")
+					.map(<_>::to_owned),
+			});
+		}
+
+		let state = query.get_first_value("state").ok_or(Error::UnknownState)?;
+		// Before it's looked up, so someone else's state isn't used up either
+		match expected_state {
+			Some(expected) if constant_time_eq(state.as_bytes(), expected.as_bytes()) => (),
+			_ => return Err(Error::UnknownState),
+		}
+
+		let pending = self
+			.pending
+			.lock()
+			.unwrap()
+			.remove(state)
+			.ok_or(Error::UnknownState)?;
+
+		if pending.started.elapsed() >= PENDING_LIFETIME {
+			return Err(Error::UnknownState);
+		}
+
+		let code = query.get_first_value("code").ok_or(Error::MissingCode)?;
+
+		let mut body = format!(
+			"grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+			Query::url_encode(code),
+			Query::url_encode(&self.provider.redirect_uri),
+			Query::url_encode(&self.provider.client_id),
+			pending.verifier
+		);
+		if let Some(secret) = &self.provider.client_secret {
+			body.push_str(&format!("&client_secret={}", Query::url_encode(secret)));
+		}
+
+		let response = self
+			.transport
+			.post_form(&self.provider.token_endpoint, body)
+			.await
+			.map_err(Error::Transport)?;
+		let tokens: TokenResponse =
+			serde_json::from_slice(&response).map_err(|_| Error::MalformedTokenResponse)?;
+
+		let id_token = tokens.id_token.ok_or(Error::MalformedTokenResponse)?;
+		let claims = decode_id_token(&id_token)?;
+		self.validate(&claims, &pending.nonce)?;
+
+		Ok(Authenticated {
+			claims,
+			access_token: tokens.access_token,
+		})
+	}
+
+	/// [Client::callback] and then log in the [Users] entry linked to the
+	/// account. If there isn't one you get [Error::NotLinked] with the
+	/// claims, so you can register or link them.
+	pub async fn login(
+		&self,
+		users: &Users,
+		query: &Query,
+		expected_state: Option<&str>,
+	) -> Result<Session, Error> {
+		let authenticated = self.callback(query, expected_state).await?;
+
+		match users
+			.login_external(&authenticated.claims.external_id())
+			.await
+		{
+			Some(session) => Ok(session),
+			None => Err(Error::NotLinked(Box::new(authenticated.claims))),
+		}
+	}
+
+	fn validate(&self, claims: &Claims, nonce: &str) -> Result<(), Error> {
+		if claims.iss != self.provider.issuer {
+			return Err(Error::InvalidClaim("iss"));
+		}
+
+		if !claims.aud.contains(&self.provider.client_id) {
+			return Err(Error::InvalidClaim("aud"));
+		}
+
+		if claims.aud.len() > 1 && claims.azp.as_deref() != Some(self.provider.client_id.as_str()) {
+			return Err(Error::InvalidClaim("azp"));
+		}
+
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		// They're from the token, so they could be anything, even u64::MAX
+		if claims.exp.saturating_add(LEEWAY) < now {
+			return Err(Error::InvalidClaim("exp"));
+		}
+
+		// It can't be from the future, or from before this login started
+		let oldest = PENDING_LIFETIME.as_secs() + LEEWAY;
+		if claims.iat > now.saturating_add(LEEWAY) || claims.iat.saturating_add(oldest) < now {
+			return Err(Error::InvalidClaim("iat"));
+		}
+
+		if claims.nonce.as_deref() != Some(nonce) {
+			return Err(Error::InvalidClaim("nonce"));
+		}
+
+		Ok(())
+	}
+}
+
+/// Compare without stopping at the first difference, so the time it takes
+/// doesn't say how much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Pull the claims out of a JWT without checking its signature
+fn decode_id_token(token: &str) -> Result<Claims, Error> {
+	let mut parts = token.split('.');
+	let payload = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+		(Some(_), Some(payload), Some(_), None) => payload,
+		_ => return Err(Error::MalformedIdToken),
+	};
+
+	let json = URL_SAFE_NO_PAD
+		.decode(payload.trim_end_matches('='))
+		.map_err(|_| Error::MalformedIdToken)?;
+
+	serde_json::from_slice(&json).map_err(|_| Error::MalformedIdToken)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+	#[error("the provider returned an error: {error}")]
+	Provider {
+		error: String,
+		description: Option<String>,
+	},
+	#[error("the state is unknown, expired, or from another browser")]
+	UnknownState,
+	#[error("the provider did not return a code")]
+	MissingCode,
+	#[error("the token request failed: {0}")]
+	Transport(String),
+	#[error("the token response was not understood")]
+	MalformedTokenResponse,
+	#[error("the ID token was not understood")]
+	MalformedIdToken,
+	#[error("the ID token's {0} claim is not valid")]
+	InvalidClaim(&'static str),
+	#[error("the account is not linked to a user")]
+	NotLinked(Box<Claims>),
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		sync::Mutex,
+		time::{SystemTime, UNIX_EPOCH},
+	};
+
+	use async_trait::async_trait;
+	use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+	use sha2::{Digest, Sha256};
+
+	use super::{Client, Error, Provider, Transport};
+	use crate::{
+		query::Query,
+		users::{ExternalId, Users},
+	};
+
+	const ISSUER: &str = "https://id.example.com";
+
+	/// Stands in for the provider's token endpoint. Hands out an ID token for
+	/// `alice` if the code and PKCE verifier are right.
+	struct MockProvider {
+		challenge: Mutex<String>,
+		nonce: Mutex<String>,
+		exp: Mutex<u64>,
+		iat: Mutex<u64>,
+		issuer: &'static str,
+	}
+
+	#[async_trait]
+	impl Transport for MockProvider {
+		async fn post_form(&self, url: &str, body: String) -> Result<Vec<u8>, String> {
+			assert_eq!(url, "https://id.example.com/token");
+			let form: Query = body.parse().unwrap();
+
+			if form.get_first_value("code") != Some("good-code") {
+				return Err(String::from("400 invalid_grant"));
+			}
+
+			let verifier = form.get_first_value("code_verifier").unwrap();
+			let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+			if challenge != *self.challenge.lock().unwrap() {
+				return Err(String::from("400 invalid_grant"));
+			}
+
+			let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#);
+			let claims = URL_SAFE_NO_PAD.encode(format!(
+				r#"{{"iss":"{}","sub":"alice","aud":"client","exp":{},"iat":{},"nonce":"{}","email":"alice@example.com"}}"#,
+				self.issuer,
+				self.exp.lock().unwrap(),
+				self.iat.lock().unwrap(),
+				self.nonce.lock().unwrap()
+			));
+
+			Ok(format!(
+				r#"{{"access_token":"at","token_type":"Bearer","id_token":"{header}.{claims}."}}"#
+			)
+			.into_bytes())
+		}
+	}
+
+	fn client(issuer: &'static str) -> Client<MockProvider> {
+		let provider = Provider {
+			issuer: String::from(ISSUER),
+			authorization_endpoint: String::from("https://id.example.com/authorize"),
+			token_endpoint: String::from("https://id.example.com/token"),
+			client_id: String::from("client"),
+			client_secret: None,
+			redirect_uri: String::from("https://app.example.com/callback"),
+			scopes: vec![String::from("email")],
+		};
+
+		Client::new(
+			provider,
+			MockProvider {
+				challenge: Mutex::new(String::new()),
+				nonce: Mutex::new(String::new()),
+				exp: Mutex::new(99999999999),
+				iat: Mutex::new(now()),
+				issuer,
+			},
+		)
+	}
+
+	/// Start a login and play the provider's part of remembering what it was
+	/// asked for. Returns the state.
+	fn start(client: &Client<MockProvider>) -> String {
+		let authorization = client.authorize();
+		let (_, query) = authorization.url.split_once('?').unwrap();
+		let query: Query = query.parse().unwrap();
+
+		assert_eq!(query.get_first_value("scope"), Some("openid email"));
+		assert_eq!(query.get_first_value("code_challenge_method"), Some("S256"));
+		*client.transport.challenge.lock().unwrap() =
+			query.get_first_value("code_challenge").unwrap().to_owned();
+		*client.transport.nonce.lock().unwrap() =
+			query.get_first_value("nonce").unwrap().to_owned();
+
+		authorization.state
+	}
+
+	fn callback(state: &str, code: &str) -> Query {
+		format!("state={state}&code={code}").parse().unwrap()
+	}
+
+	#[tokio::test]
+	async fn login_and_link() {
+		let client = client(ISSUER);
+		let users = Users::new();
+
+		let state = start(&client);
+		match client
+			.login(&users, &callback(&state, "good-code"), Some(&state))
+			.await
+		{
+			Err(Error::NotLinked(claims)) => {
+				assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+				users
+					.register_external(claims.email.clone(), "alice".into(), claims.external_id())
+					.await
+					.unwrap();
+			}
+			Err(err) => panic!("expected NotLinked, got {err}"),
+			Ok(_) => panic!("logged in without being linked"),
+		}
+
+		let state = start(&client);
+		let session = client
+			.login(&users, &callback(&state, "good-code"), Some(&state))
+			.await
+			.unwrap();
+		assert_eq!(session.stub.username, "alice");
+
+		// They have no password to log in with
+		assert!(users.login("alice".into(), String::new()).await.is_none());
+		assert!(users
+			.stub_by_external(&ExternalId::new(ISSUER, "alice"))
+			.await
+			.is_some());
+	}
+
+	#[tokio::test]
+	async fn rejects_bad_callbacks() {
+		let client = client(ISSUER);
+
+		let state = start(&client);
+		assert!(matches!(
+			client
+				.callback(&callback("forged", "good-code"), Some("forged"))
+				.await,
+			Err(Error::UnknownState)
+		));
+
+		// The state can't be used twice
+		client
+			.callback(&callback(&state, "good-code"), Some(&state))
+			.await
+			.unwrap();
+		assert!(matches!(
+			client
+				.callback(&callback(&state, "good-code"), Some(&state))
+				.await,
+			Err(Error::UnknownState)
+		));
+
+		let state = start(&client);
+		assert!(matches!(
+			client
+				.callback(&callback(&state, "bad-code"), Some(&state))
+				.await,
+			Err(Error::Transport(_))
+		));
+
+		// A state from a login another browser started
+		let ours = start(&client);
+		let theirs = start(&client);
+		assert!(matches!(
+			client
+				.callback(&callback(&theirs, "good-code"), Some(&ours))
+				.await,
+			Err(Error::UnknownState)
+		));
+		assert!(matches!(
+			client.callback(&callback(&theirs, "good-code"), None).await,
+			Err(Error::UnknownState)
+		));
+		// Which can still be finished by the browser that started it
+		client
+			.callback(&callback(&theirs, "good-code"), Some(&theirs))
+			.await
+			.unwrap();
+
+		let denied: Query = "error=access_denied".parse().unwrap();
+		assert!(matches!(
+			client.callback(&denied, None).await,
+			Err(Error::Provider { .. })
+		));
+	}
+
+	#[tokio::test]
+	async fn checks_claims() {
+		let client = client("https://evil.example.com");
+
+		let state = start(&client);
+		assert!(matches!(
+			client
+				.callback(&callback(&state, "good-code"), Some(&state))
+				.await,
+			Err(Error::InvalidClaim("iss"))
+		));
+
+		let client = self::client(ISSUER);
+		let state = start(&client);
+		*client.transport.nonce.lock().unwrap() = String::from("replayed");
+		assert!(matches!(
+			client
+				.callback(&callback(&state, "good-code"), Some(&state))
+				.await,
+			Err(Error::InvalidClaim("nonce"))
+		));
+
+		for iat in [now() + 3600, now() - 3600, u64::MAX] {
+			let client = self::client(ISSUER);
+			let state = start(&client);
+			*client.transport.iat.lock().unwrap() = iat;
+			assert!(matches!(
+				client
+					.callback(&callback(&state, "good-code"), Some(&state))
+					.await,
+				Err(Error::InvalidClaim("iat"))
+			));
+		}
+
+		// Doesn't overflow, it's just a token that doesn't expire
+		let client = self::client(ISSUER);
+		let state = start(&client);
+		*client.transport.exp.lock().unwrap() = u64::MAX;
+		client
+			.callback(&callback(&state, "good-code"), Some(&state))
+			.await
+			.unwrap();
+	}
+
+	#[test]
+	fn state_cookie() {
+		let authorization = client(ISSUER).authorize();
+		let cookie = authorization.state_cookie();
+
+		assert!(cookie.starts_with(&format!("oauth_state={};", authorization.state)));
+		assert!(cookie.contains("HttpOnly"));
+		assert!(cookie.contains("SameSite=Lax"));
+		assert!(super::state_removal_cookie().starts_with("oauth_state=;"));
+	}
+
+	#[tokio::test]
+	async fn forgets_oldest_pending() {
+		let client = client(ISSUER).max_pending(2);
+
+		let first = start(&client);
+		let _second = start(&client);
+		let third = start(&client);
+		assert_eq!(client.pending.lock().unwrap().len(), 2);
+
+		assert!(matches!(
+			client
+				.callback(&callback(&first, "good-code"), Some(&first))
+				.await,
+			Err(Error::UnknownState)
+		));
+		client
+			.callback(&callback(&third, "good-code"), Some(&third))
+			.await
+			.unwrap();
+	}
+
+	fn now() -> u64 {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs()
+	}
+}
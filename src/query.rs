@@ -1,14 +1,107 @@
-use std::{str::FromStr, vec::IntoIter};
+use std::{
+	borrow::Cow,
+	collections::{HashMap, HashSet},
+	str::FromStr,
+	vec::IntoIter,
+};
 
 use thiserror::Error;
 
+mod borrowed;
+mod de;
+mod encode;
+mod ser;
+
+pub use borrowed::{ParameterRef, QueryRef};
+pub use de::{from_query, from_str, MAX_DEPTH};
+pub use encode::EncodeSet;
+pub use ser::to_string;
+
 ///blah
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Query {
 	parameters: Vec<Parameter>,
+	/// The keys as they were before decoding, one for each parameter
+	raw_keys: Vec<String>,
 }
 
 impl Query {
+	/// An empty query, to add parameters to and then turn into a string with
+	/// `to_string`.
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::Query;
+	///
+	///let mut query = Query::new();
+	///query.push_value("next", "/posts?page=2");
+	///query.push_bool("preview");
+	///
+	///assert_eq!(query.to_string(), "next=%2fposts%3fpage%3d2&preview");
+	///```
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a key-value pair to the end, after any others with the same key
+	pub fn push_value<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+		let key = key.into();
+		self.raw_keys.push(Self::url_encode(&key));
+		self.parameters.push(Parameter::Value(key, value.into()));
+	}
+
+	/// Add a bool to the end
+	pub fn push_bool<S: Into<String>>(&mut self, name: S) {
+		let name = name.into();
+		self.raw_keys.push(Self::url_encode(&name));
+		self.parameters.push(Parameter::Bool(name));
+	}
+
+	/// Replace every parameter with this key, bools included, with one
+	/// key-value pair. It goes where the first of them was, or on the end if
+	/// there were none.
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::Query;
+	///
+	///let mut query: Query = "page=1&sort=new&page=3".parse().unwrap();
+	///query.set("page", "2");
+	///
+	///assert_eq!(query.to_string(), "page=2&sort=new");
+	///```
+	pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+		let key = key.into();
+		let position = self.parameters.iter().position(|param| param.key() == key);
+		self.remove(&key);
+
+		let position = position.unwrap_or(self.parameters.len());
+		self.raw_keys.insert(position, Self::url_encode(&key));
+		self.parameters
+			.insert(position, Parameter::Value(key, value.into()));
+	}
+
+	/// Remove every parameter with this key, bools included
+	pub fn remove<S: AsRef<str>>(&mut self, key: S) {
+		let mut index = 0;
+		while index < self.parameters.len() {
+			if self.parameters[index].key() == key.as_ref() {
+				self.parameters.remove(index);
+				self.raw_keys.remove(index);
+			} else {
+				index += 1;
+			}
+		}
+	}
+
+	/// The keys and bool names before they were decoded, in the same order as
+	/// the parameters.
+	pub fn raw_keys(&self) -> &[String] {
+		&self.raw_keys
+	}
+
 	/// Returns true if the query has a parameter with the given key, whether it's a bool or key-value pair.
 	///
 	/// # Examples
@@ -104,6 +197,79 @@ impl Query {
 		None
 	}
 
+	/// Every value given for a key, in order. Bools with the name are skipped.
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::Query;
+	///
+	///let query: Query = "tag=a&page=1&tag=b&tag".parse().unwrap();
+	///
+	///assert_eq!(query.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+	///```
+	pub fn get_all<'a, S: AsRef<str> + 'a>(&'a self, key: S) -> impl Iterator<Item = &'a str> {
+		self.parameters.iter().filter_map(move |param| match param {
+			Parameter::Value(param_key, value) if param_key == key.as_ref() => Some(value.as_str()),
+			_ => None,
+		})
+	}
+
+	/// Parse every value given for a key, see [Query::get_all]
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::Query;
+	///
+	///let query: Query = "id=1&id=two&id=3".parse().unwrap();
+	///let ids: Vec<Result<u32, _>> = query.parse_all("id").collect();
+	///
+	///assert_eq!(ids[0], Ok(1));
+	///assert!(ids[1].is_err());
+	///assert_eq!(ids[2], Ok(3));
+	///```
+	pub fn parse_all<'a, S: AsRef<str> + 'a, T: FromStr>(
+		&'a self,
+		key: S,
+	) -> impl Iterator<Item = Result<T, <T as FromStr>::Err>> + 'a {
+		self.get_all(key).map(|v| v.parse())
+	}
+
+	/// The parameters in the order they came in
+	pub fn iter(&self) -> std::slice::Iter<'_, Parameter> {
+		self.parameters.iter()
+	}
+
+	/// How many parameters there are, counting repeated keys each time
+	pub fn len(&self) -> usize {
+		self.parameters.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.parameters.is_empty()
+	}
+
+	/// Each key and bool name once, in the order they first appear
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::Query;
+	///
+	///let query: Query = "tag=a&page=1&tag=b&preview".parse().unwrap();
+	///
+	///assert_eq!(query.keys().collect::<Vec<_>>(), vec!["tag", "page", "preview"]);
+	///```
+	pub fn keys(&self) -> impl Iterator<Item = &str> {
+		let mut seen = HashSet::new();
+
+		self.parameters
+			.iter()
+			.map(Parameter::key)
+			.filter(move |key| seen.insert(*key))
+	}
+
 	/// Parses the first value from a key-value pair, if one is found, and returns
 	/// the result, or None if it can't be found.
 	///
@@ -148,53 +314,130 @@ impl Query {
 		urlencoded: S,
 		plus_as_space: bool,
 	) -> Result<String, QueryParseError> {
-		let mut uncoded: Vec<u8> = vec![];
+		Self::url_decode_with(urlencoded, plus_as_space, DecodeMode::Lenient)
+	}
 
-		let mut chars = urlencoded.as_ref().chars().peekable();
-		loop {
-			match chars.next() {
-				Some('+') => match plus_as_space {
-					true => uncoded.push(b' '),
-					false => uncoded.push(b'+'),
-				},
-				Some('%') => match chars.peek() {
-					Some(c) if c.is_ascii_hexdigit() => {
-						let upper = chars.next().unwrap();
-
-						if let Some(lower) = chars.peek() {
-							if lower.is_ascii_hexdigit() {
-								let upper = upper.to_digit(16).unwrap();
-								let lower = chars.next().unwrap().to_digit(16).unwrap();
-
-								uncoded.push(upper as u8 * 16 + lower as u8);
-								continue;
-							}
-						}
-
-						uncoded.push(b'%');
-						uncoded.extend_from_slice(&Self::char_bytes(upper));
+	/// [Query::url_decode] with a choice of what to do with malformed escapes
+	/// and invalid UTF-8, see [DecodeMode]. Offsets in errors are bytes into
+	/// `urlencoded`.
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::{DecodeMode, Query, QueryParseError};
+	///
+	///assert_eq!(
+	///    Query::url_decode_with("invalid%1Z", true, DecodeMode::Strict),
+	///    Err(QueryParseError::MalformedEscape { offset: 7 })
+	///);
+	///assert_eq!(
+	///    Query::url_decode_with("bad%FFbyte", true, DecodeMode::Lossy),
+	///    Ok(String::from("bad\u{FFFD}byte"))
+	///);
+	///```
+	pub fn url_decode_with<S: AsRef<str>>(
+		urlencoded: S,
+		plus_as_space: bool,
+		mode: DecodeMode,
+	) -> Result<String, QueryParseError> {
+		let bytes = urlencoded.as_ref().as_bytes();
+		let mut uncoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+		let hex = |index: usize| bytes.get(index).and_then(|b| (*b as char).to_digit(16));
+
+		let mut index = 0;
+		while index < bytes.len() {
+			match bytes[index] {
+				b'+' if plus_as_space => uncoded.push(b' '),
+				b'%' => match (hex(index + 1), hex(index + 2)) {
+					(Some(upper), Some(lower)) => {
+						uncoded.push((upper * 16 + lower) as u8);
+						index += 2;
 					}
-					_ => {
-						uncoded.push(b'%');
+					_ if mode == DecodeMode::Strict => {
+						return Err(QueryParseError::MalformedEscape { offset: index })
 					}
+					_ => uncoded.push(b'%'),
 				},
-				Some(c) => {
-					uncoded.extend_from_slice(&Self::char_bytes(c));
-				}
-				None => {
-					return Ok(String::from_utf8(uncoded).map_err(|_| QueryParseError::InvalidUtf8)?)
-				}
+				b => uncoded.push(b),
 			}
+
+			index += 1;
+		}
+
+		match String::from_utf8(uncoded) {
+			Ok(decoded) => Ok(decoded),
+			Err(err) if mode == DecodeMode::Lossy => {
+				Ok(String::from_utf8_lossy(err.as_bytes()).into_owned())
+			}
+			Err(_) => Err(QueryParseError::InvalidUtf8),
 		}
 	}
 
+	/// [Query::url_decode_with] that only allocates if there's something to
+	/// decode
+	pub(crate) fn decode_cow(
+		urlencoded: &str,
+		plus_as_space: bool,
+		mode: DecodeMode,
+	) -> Result<Cow<'_, str>, QueryParseError> {
+		let needs_decoding = urlencoded
+			.bytes()
+			.any(|b| b == b'%' || (plus_as_space && b == b'+'));
+
+		if needs_decoding {
+			Self::url_decode_with(urlencoded, plus_as_space, mode).map(Cow::Owned)
+		} else {
+			Ok(Cow::Borrowed(urlencoded))
+		}
+	}
+
+	/// Parse a query string like `from_str` does, choosing how strict to be
+	/// about the encoding. `from_str` is [DecodeMode::Lenient].
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::{DecodeMode, Query, QueryParseError};
+	///
+	///let err = Query::parse_with("ok=1&100%25=100%", DecodeMode::Strict).unwrap_err();
+	///assert_eq!(
+	///    err,
+	///    QueryParseError::Parameter {
+	///        key: String::from("100%25"),
+	///        source: Box::new(QueryParseError::MalformedEscape { offset: 15 }),
+	///    }
+	///);
+	///```
+	pub fn parse_with(s: &str, mode: DecodeMode) -> Result<Self, QueryParseError> {
+		Ok(QueryRef::parse_with(s, mode)?.into_owned())
+	}
+
+	/// Percent-encode a string for one part of a URL, see [EncodeSet]
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::{EncodeSet, Query};
+	///
+	///assert_eq!(Query::url_encode_with("a&b <c>", EncodeSet::Query), "a&b%20%3Cc%3E");
+	///assert_eq!(Query::url_encode_with("a&b <c>", EncodeSet::Form), "a%26b+%3Cc%3E");
+	///```
+	pub fn url_encode_with<S: AsRef<str>>(raw: S, set: EncodeSet) -> String {
+		set.encode(raw)
+	}
+
 	/// Process a string, encoding the reserved URL characters below into their
 	/// percent equivalent. Any character outside of the ASCII printables are
 	/// also percent encoded.
 	///
 	/// The following characters are reserved and will be encoded as %xx where
 	/// x is a lowercase hex digit:
-	/// `! # $ % ' ( ) * + , / : ; = ? @ [ ]`
+	/// `! # $ % & ' ( ) * + , / : ; = ? @ [ ]`
+	///
+	/// `&` didn't use to be encoded, so a value containing one was split in two
+	/// when the query was written out. If you relied on that, use
+	/// [Query::url_encode_with] and [EncodeSet::Query].
 	///
 	/// # Returns
 	///
@@ -209,11 +452,12 @@ impl Query {
 	///assert_eq!(Query::url_encode("encode me spaces!"), String::from("encode%20me%20spaces%21"));
 	///assert_eq!(Query::url_encode("🥺"), String::from("%f0%9f%a5%ba"));
 	///assert_eq!(Query::url_encode("one+two"), String::from("one%2btwo"));
+	///assert_eq!(Query::url_encode("this&that"), String::from("this%26that"));
 	///```
 	pub fn url_encode<S: AsRef<str>>(raw: S) -> String {
 		let mut encoded = String::new();
 		let mut chars = raw.as_ref().chars().peekable();
-		let should_encode = |c: char| !c.is_ascii_graphic() || "!#$%'()*+,/:;=?@[]".contains(c);
+		let should_encode = |c: char| !c.is_ascii_graphic() || "!#$%&'()*+,/:;=?@[]".contains(c);
 
 		loop {
 			match chars.next() {
@@ -241,24 +485,25 @@ impl Query {
 impl std::str::FromStr for Query {
 	type Err = QueryParseError;
 
+	/// Parse a query string. Keys, bool names, and values are all decoded.
+	/// Empty segments, like from `a=1&&b=2` or a trailing `&`, are skipped.
+	///
+	/// Errors come wrapped in [QueryParseError::Parameter] with the raw key of
+	/// the parameter that failed.
+	///
+	/// # Examples
+	///
+	///```rust
+	///use mavourings::query::Query;
+	///
+	///let query: Query = "first%20name=gen&&remember+me&".parse().unwrap();
+	///
+	///assert_eq!(query.get_first_value("first name"), Some("gen"));
+	///assert!(query.has_bool("remember me"));
+	///assert_eq!(query.raw_keys(), ["first%20name", "remember+me"]);
+	///```
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		let mut parameters: Vec<Parameter> = vec![];
-		let splits = s.split('&');
-
-		for split in splits {
-			let splits: Vec<&str> = split.splitn(2, '=').collect();
-
-			match splits.len() {
-				1 => parameters.push(Parameter::Bool(splits[0].into())),
-				2 => parameters.push(Parameter::Value(
-					splits[0].into(),
-					Self::url_decode(splits[1], true)?,
-				)),
-				_ => unreachable!(),
-			}
-		}
-
-		Ok(Self { parameters })
+		Ok(QueryRef::parse(s)?.into_owned())
 	}
 }
 
@@ -272,14 +517,178 @@ impl IntoIterator for Query {
 	}
 }
 
+/// Encodes the keys and values again. Parsing a query and writing it back out
+/// gives an equivalent query, though what was encoded might differ.
+impl std::fmt::Display for Query {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for (index, param) in self.parameters.iter().enumerate() {
+			if index > 0 {
+				write!(f, "&")?;
+			}
+
+			match param {
+				Parameter::Bool(name) => write!(f, "{}", Self::url_encode(name))?,
+				Parameter::Value(key, value) => {
+					write!(f, "{}={}", Self::url_encode(key), Self::url_encode(value))?
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl<'a> IntoIterator for &'a Query {
+	type Item = &'a Parameter;
+
+	type IntoIter = std::slice::Iter<'a, Parameter>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.parameters.iter()
+	}
+}
+
+/// Every key with all of its values. A bool is a key with no values.
+///
+/// # Examples
+///
+///```rust
+///use std::collections::HashMap;
+///use mavourings::query::Query;
+///
+///let query: Query = "tag=a&tag=b&preview".parse().unwrap();
+///let map = HashMap::<String, Vec<String>>::from(&query);
+///
+///assert_eq!(map["tag"], vec!["a", "b"]);
+///assert!(map["preview"].is_empty());
+///```
+impl From<&Query> for HashMap<String, Vec<String>> {
+	fn from(query: &Query) -> Self {
+		let mut map: HashMap<String, Vec<String>> = HashMap::new();
+
+		for param in query {
+			let values = map.entry(param.key().to_owned()).or_default();
+			if let Parameter::Value(_, value) = param {
+				values.push(value.clone());
+			}
+		}
+
+		map
+	}
+}
+
+impl From<Query> for HashMap<String, Vec<String>> {
+	fn from(query: Query) -> Self {
+		let mut map: HashMap<String, Vec<String>> = HashMap::new();
+
+		for param in query {
+			match param {
+				Parameter::Bool(name) => {
+					map.entry(name).or_default();
+				}
+				Parameter::Value(key, value) => map.entry(key).or_default().push(value),
+			}
+		}
+
+		map
+	}
+}
+
 #[derive(Debug)]
 pub enum Parameter {
 	Bool(String),
 	Value(String, String),
 }
 
+impl Parameter {
+	/// The key of a key-value pair or the name of a bool
+	pub fn key(&self) -> &str {
+		match self {
+			Parameter::Bool(name) => name,
+			Parameter::Value(key, _) => key,
+		}
+	}
+}
+
+/// How [Query::url_decode_with] and [Query::parse_with] handle bad input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+	/// Malformed escapes like `%1Z` are kept as they are, invalid UTF-8 is an
+	/// error. This is what [Query::url_decode] does.
+	#[default]
+	Lenient,
+	/// Malformed escapes and invalid UTF-8 are both errors
+	Strict,
+	/// Never fails. Malformed escapes are kept and invalid UTF-8 becomes U+FFFD
+	Lossy,
+}
+
 #[derive(Error, Debug, PartialEq)]
 pub enum QueryParseError {
 	#[error("the query did not resolve to valid utf8")]
 	InvalidUtf8,
+	#[error("malformed percent-escape at byte {offset}")]
+	MalformedEscape { offset: usize },
+	/// Parsing a query wraps decoding errors in this to say which parameter
+	/// failed, so invalid UTF-8 from `from_str` is
+	/// `Parameter { source: InvalidUtf8, .. }` rather than a bare
+	/// [QueryParseError::InvalidUtf8]. A [QueryParseError::MalformedEscape]
+	/// inside it has its offset into the whole query string, which tells you
+	/// if it was in the key or the value.
+	#[error("the parameter {key:?} could not be decoded: {source}")]
+	Parameter {
+		/// The key as it was before decoding
+		key: String,
+		source: Box<QueryParseError>,
+	},
+}
+
+/// An error from [from_str] or [to_string]
+#[derive(Error, Debug, PartialEq)]
+#[error("{}", if field.is_empty() { message.clone() } else { format!("{field}: {message}") })]
+pub struct QuerySerdeError {
+	/// The key that failed, like `address[city]`. Empty if the error isn't
+	/// about one field, like a missing field of the outermost struct.
+	pub field: String,
+	pub message: String,
+}
+
+impl QuerySerdeError {
+	/// Put the error under `key`, which is nested in the key it was at.
+	fn at(mut self, key: &str) -> Self {
+		self.field = match self.field.split_once('[') {
+			_ if self.field.is_empty() => key.to_owned(),
+			Some((head, rest)) => format!("{key}[{head}][{rest}"),
+			None => format!("{key}[{}]", self.field),
+		};
+		self
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Query;
+
+	#[test]
+	fn round_trip() {
+		let raw = "first+name=gen&tags=a%26b&remember%20me&empty=";
+		let mut query: Query = raw.parse().unwrap();
+		assert_eq!(
+			query.to_string(),
+			"first%20name=gen&tags=a%26b&remember%20me&empty="
+		);
+
+		query.remove("empty");
+		query.set("first name", "genny");
+		query.push_value("tags", "c");
+
+		let reparsed: Query = query.to_string().parse().unwrap();
+		assert_eq!(reparsed.get_first_value("first name"), Some("genny"));
+		assert!(reparsed.has_bool("remember me"));
+		assert!(!reparsed.has("empty"));
+		assert_eq!(
+			reparsed.raw_keys(),
+			["first%20name", "tags", "remember%20me", "tags"]
+		);
+	}
 }
@@ -0,0 +1,250 @@
+use std::borrow::Cow;
+
+use super::{DecodeMode, Parameter, Query, QueryParseError};
+
+/// A [Query] that borrows from the string it was parsed from. Keys and values
+/// are only copied if they have something to decode, a `%` or a `+`, which
+/// makes it cheaper when you're parsing every request.
+///
+/// # Examples
+///
+///```rust
+///use std::borrow::Cow;
+///use mavourings::query::QueryRef;
+///
+///let query = QueryRef::parse("page=2&q=cat+pics&preview").unwrap();
+///
+///assert_eq!(query.get_first_value("page"), Some("2"));
+///assert_eq!(query.get_first_value("q"), Some("cat pics"));
+///assert!(query.has_bool("preview"));
+///
+///// Nothing to decode, so nothing was copied
+///assert!(matches!(query.iter().next().unwrap().key_cow(), Cow::Borrowed("page")));
+///```
+#[derive(Debug, Default)]
+pub struct QueryRef<'a> {
+	parameters: Vec<ParameterRef<'a>>,
+	raw_keys: Vec<&'a str>,
+}
+
+impl<'a> QueryRef<'a> {
+	/// Parse a query string the same way as [Query]'s `from_str`
+	pub fn parse(s: &'a str) -> Result<Self, QueryParseError> {
+		Self::parse_with(s, DecodeMode::Lenient)
+	}
+
+	/// See [Query::parse_with]
+	pub fn parse_with(s: &'a str, mode: DecodeMode) -> Result<Self, QueryParseError> {
+		let mut parameters = vec![];
+		let mut raw_keys = vec![];
+
+		for split in s.split('&').filter(|split| !split.is_empty()) {
+			let (raw_key, raw_value) = match split.split_once('=') {
+				None => (split, None),
+				Some((key, value)) => (key, Some(value)),
+			};
+
+			let decode = |segment: &'a str| {
+				Query::decode_cow(segment, true, mode).map_err(|source| {
+					// Make the offset relative to the whole query string, so it
+					// says whether it was the key or the value
+					let source = match source {
+						QueryParseError::MalformedEscape { offset } => {
+							QueryParseError::MalformedEscape {
+								offset: segment.as_ptr() as usize - s.as_ptr() as usize + offset,
+							}
+						}
+						source => source,
+					};
+
+					QueryParseError::Parameter {
+						key: raw_key.to_owned(),
+						source: Box::new(source),
+					}
+				})
+			};
+
+			parameters.push(match raw_value {
+				None => ParameterRef::Bool(decode(raw_key)?),
+				Some(value) => ParameterRef::Value(decode(raw_key)?, decode(value)?),
+			});
+			raw_keys.push(raw_key);
+		}
+
+		Ok(Self {
+			parameters,
+			raw_keys,
+		})
+	}
+
+	/// See [Query::has]
+	pub fn has<S: AsRef<str>>(&self, key: S) -> bool {
+		self.parameters
+			.iter()
+			.any(|param| param.key() == key.as_ref())
+	}
+
+	/// See [Query::has_bool]
+	pub fn has_bool<S: AsRef<str>>(&self, name: S) -> bool {
+		self.parameters.iter().any(
+			|param| matches!(param, ParameterRef::Bool(param_name) if param_name == name.as_ref()),
+		)
+	}
+
+	/// See [Query::get_first_value]
+	pub fn get_first_value<S: AsRef<str>>(&self, key: S) -> Option<&str> {
+		self.parameters.iter().find_map(|param| match param {
+			ParameterRef::Value(param_key, value) if param_key == key.as_ref() => {
+				Some(value.as_ref())
+			}
+			_ => None,
+		})
+	}
+
+	/// See [Query::get_all]
+	pub fn get_all<'s, S: AsRef<str> + 's>(&'s self, key: S) -> impl Iterator<Item = &'s str> {
+		self.parameters.iter().filter_map(move |param| match param {
+			ParameterRef::Value(param_key, value) if param_key == key.as_ref() => {
+				Some(value.as_ref())
+			}
+			_ => None,
+		})
+	}
+
+	pub fn iter(&self) -> std::slice::Iter<'_, ParameterRef<'a>> {
+		self.parameters.iter()
+	}
+
+	pub fn len(&self) -> usize {
+		self.parameters.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.parameters.is_empty()
+	}
+
+	/// See [Query::raw_keys]
+	pub fn raw_keys(&self) -> &[&'a str] {
+		&self.raw_keys
+	}
+
+	/// Copy everything into a [Query] that doesn't borrow
+	pub fn into_owned(self) -> Query {
+		Query {
+			parameters: self
+				.parameters
+				.into_iter()
+				.map(ParameterRef::into_owned)
+				.collect(),
+			raw_keys: self.raw_keys.into_iter().map(<_>::to_owned).collect(),
+		}
+	}
+}
+
+impl<'a> IntoIterator for QueryRef<'a> {
+	type Item = ParameterRef<'a>;
+
+	type IntoIter = std::vec::IntoIter<ParameterRef<'a>>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.parameters.into_iter()
+	}
+}
+
+/// A [Parameter] that might borrow its key and value
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterRef<'a> {
+	Bool(Cow<'a, str>),
+	Value(Cow<'a, str>, Cow<'a, str>),
+}
+
+impl<'a> ParameterRef<'a> {
+	/// The key of a key-value pair or the name of a bool
+	pub fn key(&self) -> &str {
+		self.key_cow()
+	}
+
+	/// Like [ParameterRef::key], but you can see if it was borrowed
+	pub fn key_cow(&self) -> &Cow<'a, str> {
+		match self {
+			ParameterRef::Bool(name) => name,
+			ParameterRef::Value(key, _) => key,
+		}
+	}
+
+	pub fn into_owned(self) -> Parameter {
+		match self {
+			ParameterRef::Bool(name) => Parameter::Bool(name.into_owned()),
+			ParameterRef::Value(key, value) => {
+				Parameter::Value(key.into_owned(), value.into_owned())
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::borrow::Cow;
+
+	use super::{ParameterRef, QueryRef};
+	use crate::query::{DecodeMode, QueryParseError};
+
+	#[test]
+	fn borrows_when_it_can() {
+		let err = QueryRef::parse("a=1&bad=%FF%FE").unwrap_err();
+		assert_eq!(
+			err,
+			QueryParseError::Parameter {
+				key: String::from("bad"),
+				source: Box::new(QueryParseError::InvalidUtf8),
+			}
+		);
+
+		let query = QueryRef::parse("a=1&b%20c=d+e&&flag&pct=100%").unwrap();
+		let params: Vec<_> = query.iter().cloned().collect();
+
+		assert!(matches!(
+			&params[0],
+			ParameterRef::Value(Cow::Borrowed("a"), Cow::Borrowed("1"))
+		));
+		assert!(matches!(
+			&params[1],
+			ParameterRef::Value(Cow::Owned(key), Cow::Owned(value)) if key == "b c" && value == "d e"
+		));
+		assert!(matches!(
+			&params[2],
+			ParameterRef::Bool(Cow::Borrowed("flag"))
+		));
+		assert_eq!(query.get_first_value("pct"), Some("100%"));
+		assert_eq!(query.raw_keys(), ["a", "b%20c", "flag", "pct"]);
+
+		let owned = query.into_owned();
+		assert_eq!(owned.get_first_value("b c"), Some("d e"));
+		assert_eq!(owned.raw_keys(), ["a", "b%20c", "flag", "pct"]);
+	}
+
+	#[test]
+	fn strict_rejects_malformed_escape() {
+		let malformed = |query| match QueryRef::parse_with(query, DecodeMode::Strict) {
+			Err(QueryParseError::Parameter { key, source }) => match *source {
+				QueryParseError::MalformedEscape { offset } => (key, offset),
+				source => panic!("{source:?}"),
+			},
+			other => panic!("{other:?}"),
+		};
+
+		// The offset is into the whole string, so it says which part was bad
+		assert_eq!(malformed("a=1&b%zz=2"), (String::from("b%zz"), 5));
+		assert_eq!(malformed("a=1&b=2%z"), (String::from("b"), 7));
+		assert_eq!(malformed("flag%"), (String::from("flag%"), 4));
+
+		assert!(QueryRef::parse("a=1&b=2%z").is_ok());
+	}
+
+	#[test]
+	fn lossy_replaces_invalid_utf8() {
+		let lossy = QueryRef::parse_with("bad=%FF%FE&ok=%zz", DecodeMode::Lossy).unwrap();
+		assert_eq!(lossy.get_first_value("bad"), Some("\u{FFFD}\u{FFFD}"));
+		assert_eq!(lossy.get_first_value("ok"), Some("%zz"));
+	}
+}
@@ -0,0 +1,577 @@
+use std::vec::IntoIter;
+
+use serde::de::{
+	self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+	VariantAccess, Visitor,
+};
+
+use super::{Parameter, Query, QuerySerdeError};
+
+/// Deserialize a query string into any type that implements
+/// [Deserialize](serde::Deserialize).
+///
+/// Repeated keys make sequences, a key without a value is `true`, and keys
+/// like `a[b]` fill in nested structs and maps. An empty value for an
+/// `Option` is `None`.
+///
+/// # Examples
+///
+///```rust
+///use serde::Deserialize;
+///
+///#[derive(Deserialize, Debug, PartialEq)]
+///struct Search {
+///    q: String,
+///    page: Option<u32>,
+///    tags: Vec<String>,
+///    exact: bool,
+///    filter: Filter,
+///}
+///
+///#[derive(Deserialize, Debug, PartialEq)]
+///struct Filter {
+///    after: u16,
+///}
+///
+///let search: Search =
+///    mavourings::query::from_str("q=cat+pics&page=&tags=cute&tags=small&exact&filter[after]=2020")
+///        .unwrap();
+///
+///assert_eq!(search, Search {
+///    q: String::from("cat pics"),
+///    page: None,
+///    tags: vec![String::from("cute"), String::from("small")],
+///    exact: true,
+///    filter: Filter { after: 2020 },
+///});
+///```
+pub fn from_str<T: DeserializeOwned>(s: &str) -> Result<T, QuerySerdeError> {
+	let query: Query = s.parse().map_err(|e| QuerySerdeError {
+		field: String::new(),
+		message: format!("{e}"),
+	})?;
+
+	from_query(&query)
+}
+
+/// How many segments a key like `a[b][c]` can have
+pub const MAX_DEPTH: usize = 32;
+
+/// Like [from_str] for a query that's already been parsed. Keys nested more
+/// than [MAX_DEPTH] deep are an error.
+pub fn from_query<T: DeserializeOwned>(query: &Query) -> Result<T, QuerySerdeError> {
+	let mut root = Node::default();
+
+	for parameter in &query.parameters {
+		let (key, value) = match parameter {
+			Parameter::Bool(key) => (key, None),
+			Parameter::Value(key, value) => (key, Some(value.clone())),
+		};
+
+		if key.is_empty() {
+			continue;
+		}
+
+		let segments = split_key(key);
+		// The tree is built and read by recursion, so a deep enough key would
+		// overflow the stack
+		if segments.len() > MAX_DEPTH {
+			return Err(QuerySerdeError {
+				field: segments[0].to_owned(),
+				message: format!("keys can't be nested more than {MAX_DEPTH} deep"),
+			});
+		}
+
+		root.insert(&segments, value);
+	}
+
+	T::deserialize(NodeDeserializer { node: root })
+}
+
+/// `a[b][c]` is `["a", "b", "c"]`. `[]` means the same key again, so it's
+/// dropped. A key with unbalanced brackets is taken as it is.
+fn split_key(key: &str) -> Vec<&str> {
+	let (head, mut rest) = match key.find('[') {
+		Some(open) if open > 0 => key.split_at(open),
+		_ => return vec![key],
+	};
+
+	let mut segments = vec![head];
+	while let Some(inner) = rest.strip_prefix('[') {
+		match inner.split_once(']') {
+			Some((segment, after)) => {
+				if !segment.is_empty() {
+					segments.push(segment);
+				}
+				rest = after;
+			}
+			None => return vec![key],
+		}
+	}
+
+	if rest.is_empty() {
+		segments
+	} else {
+		vec![key]
+	}
+}
+
+/// The query rearranged into a tree by key. `a=1&a=2&b[c]=3` is a root with a
+/// child `a` holding two values and a child `b` with its own child `c`.
+#[derive(Debug, Default)]
+struct Node {
+	/// Values given directly to this key. `None` is a bool, a key without `=`
+	values: Vec<Option<String>>,
+	children: Vec<(String, Node)>,
+}
+
+impl Node {
+	fn leaf(value: Option<String>) -> Self {
+		Self {
+			values: vec![value],
+			children: vec![],
+		}
+	}
+
+	fn insert(&mut self, segments: &[&str], value: Option<String>) {
+		let (first, rest) = match segments.split_first() {
+			None => return self.values.push(value),
+			Some(split) => split,
+		};
+
+		let index = match self.children.iter().position(|(name, _)| name == first) {
+			Some(index) => index,
+			None => {
+				self.children.push((first.to_string(), Node::default()));
+				self.children.len() - 1
+			}
+		};
+
+		self.children[index].1.insert(rest, value)
+	}
+
+	fn is_empty(&self) -> bool {
+		self.values.is_empty() && self.children.is_empty()
+	}
+
+	/// The first value, for things that only want one
+	fn scalar(&self) -> Result<&str, QuerySerdeError> {
+		match self.values.first() {
+			Some(Some(value)) => Ok(value),
+			Some(None) => Err(error("expected a value but it was given without an '='")),
+			None => Err(error("expected a value")),
+		}
+	}
+}
+
+fn error<S: Into<String>>(message: S) -> QuerySerdeError {
+	QuerySerdeError {
+		field: String::new(),
+		message: message.into(),
+	}
+}
+
+fn parse_bool(value: Option<&str>) -> Result<bool, QuerySerdeError> {
+	match value {
+		None => Ok(true),
+		Some("true" | "on" | "1") => Ok(true),
+		Some("false" | "off" | "0" | "") => Ok(false),
+		Some(value) => Err(error(format!("expected a bool, found {value:?}"))),
+	}
+}
+
+struct NodeDeserializer {
+	node: Node,
+}
+
+macro_rules! deserialize_parsed {
+	($($method:ident => $visit:ident,)*) => {
+		$(
+			fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+				let value = self.node.scalar()?;
+				let parsed = value.parse().map_err(|e| error(format!("{e}: {value:?}")))?;
+				visitor.$visit(parsed)
+			}
+		)*
+	};
+}
+
+impl<'de> de::Deserializer<'de> for NodeDeserializer {
+	type Error = QuerySerdeError;
+
+	fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		if !self.node.children.is_empty() {
+			self.deserialize_map(visitor)
+		} else if self.node.values.len() > 1 {
+			self.deserialize_seq(visitor)
+		} else {
+			match self.node.values.into_iter().next() {
+				None => visitor.visit_unit(),
+				Some(None) => visitor.visit_bool(true),
+				Some(Some(value)) => visitor.visit_string(value),
+			}
+		}
+	}
+
+	fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		match self.node.values.first() {
+			Some(value) => visitor.visit_bool(parse_bool(value.as_deref())?),
+			None => Err(error("expected a bool")),
+		}
+	}
+
+	deserialize_parsed! {
+		deserialize_i8 => visit_i8,
+		deserialize_i16 => visit_i16,
+		deserialize_i32 => visit_i32,
+		deserialize_i64 => visit_i64,
+		deserialize_i128 => visit_i128,
+		deserialize_u8 => visit_u8,
+		deserialize_u16 => visit_u16,
+		deserialize_u32 => visit_u32,
+		deserialize_u64 => visit_u64,
+		deserialize_u128 => visit_u128,
+		deserialize_f32 => visit_f32,
+		deserialize_f64 => visit_f64,
+		deserialize_char => visit_char,
+	}
+
+	fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_str(self.node.scalar()?)
+	}
+
+	fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		self.deserialize_str(visitor)
+	}
+
+	fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_bytes(self.node.scalar()?.as_bytes())
+	}
+
+	fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		self.deserialize_bytes(visitor)
+	}
+
+	fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		let empty = self.node.is_empty()
+			|| (self.node.children.is_empty()
+				&& self.node.values.len() == 1
+				&& self.node.values[0].as_deref() == Some(""));
+
+		if empty {
+			visitor.visit_none()
+		} else {
+			visitor.visit_some(self)
+		}
+	}
+
+	fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_unit()
+	}
+
+	fn deserialize_unit_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		visitor.visit_unit()
+	}
+
+	fn deserialize_newtype_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		visitor.visit_newtype_struct(self)
+	}
+
+	/// From the values of a repeated key, or from children like `a[0]` and `a[1]`
+	fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		let nodes: Vec<(String, Node)> = if self.node.children.is_empty() {
+			self.node
+				.values
+				.into_iter()
+				.enumerate()
+				.map(|(index, value)| (index.to_string(), Node::leaf(value)))
+				.collect()
+		} else {
+			self.node.children
+		};
+
+		visitor.visit_seq(Children::new(nodes))
+	}
+
+	fn deserialize_tuple<V: Visitor<'de>>(
+		self,
+		_len: usize,
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		self.deserialize_seq(visitor)
+	}
+
+	fn deserialize_tuple_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		_len: usize,
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		self.deserialize_seq(visitor)
+	}
+
+	fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		if self.node.children.is_empty() && !self.node.values.is_empty() {
+			return Err(error("expected nested keys like name[key]"));
+		}
+
+		visitor.visit_map(Children::new(self.node.children))
+	}
+
+	fn deserialize_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		_fields: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		self.deserialize_map(visitor)
+	}
+
+	/// Unit variants are the value, `kind=circle`. Others are a nested key
+	/// named for the variant, `kind[circle]=5`.
+	fn deserialize_enum<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		_variants: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		if self.node.children.is_empty() {
+			return visitor.visit_enum(self.node.scalar()?.to_owned().into_deserializer());
+		}
+
+		let mut children = self.node.children.into_iter();
+		match (children.next(), children.next()) {
+			(Some((name, node)), None) => visitor.visit_enum(Variant { name, node }),
+			_ => Err(error("expected one nested key naming the variant")),
+		}
+	}
+
+	fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		self.deserialize_str(visitor)
+	}
+
+	fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_unit()
+	}
+}
+
+/// The children of a node, as a map or a sequence. Errors from a child get
+/// its key as their field.
+struct Children {
+	children: IntoIter<(String, Node)>,
+	value: Option<(String, Node)>,
+}
+
+impl Children {
+	fn new(children: Vec<(String, Node)>) -> Self {
+		Self {
+			children: children.into_iter(),
+			value: None,
+		}
+	}
+}
+
+impl<'de> MapAccess<'de> for Children {
+	type Error = QuerySerdeError;
+
+	fn next_key_seed<K: DeserializeSeed<'de>>(
+		&mut self,
+		seed: K,
+	) -> Result<Option<K::Value>, Self::Error> {
+		match self.children.next() {
+			None => Ok(None),
+			Some((name, node)) => {
+				let key = seed
+					.deserialize(NodeDeserializer {
+						node: Node::leaf(Some(name.clone())),
+					})
+					.map_err(|e| e.at(&name))?;
+
+				self.value = Some((name, node));
+				Ok(Some(key))
+			}
+		}
+	}
+
+	fn next_value_seed<V: DeserializeSeed<'de>>(
+		&mut self,
+		seed: V,
+	) -> Result<V::Value, Self::Error> {
+		let (name, node) = self
+			.value
+			.take()
+			.ok_or_else(|| error("a value was asked for before its key"))?;
+
+		seed.deserialize(NodeDeserializer { node })
+			.map_err(|e| e.at(&name))
+	}
+}
+
+impl<'de> SeqAccess<'de> for Children {
+	type Error = QuerySerdeError;
+
+	fn next_element_seed<T: DeserializeSeed<'de>>(
+		&mut self,
+		seed: T,
+	) -> Result<Option<T::Value>, Self::Error> {
+		match self.children.next() {
+			None => Ok(None),
+			Some((name, node)) => seed
+				.deserialize(NodeDeserializer { node })
+				.map(Some)
+				.map_err(|e| e.at(&name)),
+		}
+	}
+}
+
+struct Variant {
+	name: String,
+	node: Node,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+	type Error = QuerySerdeError;
+	type Variant = NodeDeserializer;
+
+	fn variant_seed<V: DeserializeSeed<'de>>(
+		self,
+		seed: V,
+	) -> Result<(V::Value, Self::Variant), Self::Error> {
+		let variant = seed.deserialize(self.name.into_deserializer())?;
+		Ok((variant, NodeDeserializer { node: self.node }))
+	}
+}
+
+impl<'de> VariantAccess<'de> for NodeDeserializer {
+	type Error = QuerySerdeError;
+
+	fn unit_variant(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+		self,
+		seed: T,
+	) -> Result<T::Value, Self::Error> {
+		seed.deserialize(self)
+	}
+
+	fn tuple_variant<V: Visitor<'de>>(
+		self,
+		_len: usize,
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		de::Deserializer::deserialize_seq(self, visitor)
+	}
+
+	fn struct_variant<V: Visitor<'de>>(
+		self,
+		_fields: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		de::Deserializer::deserialize_map(self, visitor)
+	}
+}
+
+impl de::Error for QuerySerdeError {
+	fn custom<T: std::fmt::Display>(msg: T) -> Self {
+		error(msg.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use serde::Deserialize;
+
+	use super::{from_str, split_key};
+	use crate::query::QuerySerdeError;
+
+	#[derive(Debug, Deserialize, PartialEq)]
+	#[serde(rename_all = "lowercase")]
+	enum Shape {
+		Square,
+		Circle { radius: u32 },
+	}
+
+	#[derive(Debug, Deserialize, PartialEq)]
+	struct Form {
+		name: String,
+		#[serde(default)]
+		remember: bool,
+		shape: Shape,
+		outline: Option<Shape>,
+		ids: Vec<u8>,
+		extra: HashMap<String, String>,
+	}
+
+	#[test]
+	fn nested_keys() {
+		assert_eq!(split_key("a"), vec!["a"]);
+		assert_eq!(split_key("a[b][c]"), vec!["a", "b", "c"]);
+		assert_eq!(split_key("a[]"), vec!["a"]);
+		assert_eq!(split_key("a[b"), vec!["a[b"]);
+		assert_eq!(split_key("[a]"), vec!["[a]"]);
+		assert_eq!(split_key("a[b]c"), vec!["a[b]c"]);
+	}
+
+	#[test]
+	fn deserialize() {
+		let form: Form = from_str(
+			"name=gen&remember=on&shape[circle][radius]=4&outline=square&ids[]=1&ids[]=2&extra%5Bk%5D=v",
+		)
+		.unwrap();
+
+		assert_eq!(
+			form,
+			Form {
+				name: String::from("gen"),
+				remember: true,
+				shape: Shape::Circle { radius: 4 },
+				outline: Some(Shape::Square),
+				ids: vec![1, 2],
+				extra: HashMap::from([(String::from("k"), String::from("v"))]),
+			}
+		);
+	}
+
+	#[test]
+	fn limits_nesting() {
+		let deep = format!("a{}=1", "[x]".repeat(100_000));
+		let err = from_str::<HashMap<String, String>>(&deep).unwrap_err();
+		assert_eq!(err.field, "a");
+
+		let fits = format!("a{}=1", "[x]".repeat(super::MAX_DEPTH - 1));
+		assert!(from_str::<HashMap<String, serde::de::IgnoredAny>>(&fits).is_ok());
+	}
+
+	#[test]
+	fn errors_name_the_field() {
+		let err = from_str::<Form>("name=gen&shape=square&ids=1&ids=300&extra[k]=v").unwrap_err();
+		assert_eq!(err.field, "ids[1]");
+
+		let err = from_str::<Form>("name=gen&shape=square&ids=1&extra=v").unwrap_err();
+		assert_eq!(err.field, "extra");
+
+		let err = from_str::<Form>("name=gen&shape=triangle&ids=1").unwrap_err();
+		assert_eq!(err.field, "shape");
+
+		let err = from_str::<Form>("shape=square").unwrap_err();
+		assert_eq!(
+			err,
+			QuerySerdeError {
+				field: String::new(),
+				message: String::from("missing field `name`"),
+			}
+		);
+	}
+}
@@ -0,0 +1,593 @@
+use serde::{
+	ser::{self, Impossible},
+	Serialize,
+};
+
+use super::{Query, QuerySerdeError};
+
+/// Serialize a struct or map into a query string, the opposite of
+/// [from_str](super::from_str).
+///
+/// Sequences repeat the key, nested structs and maps get keys like `a[b]`,
+/// and `None` is left out. Unit enum variants are written as their name.
+///
+/// # Examples
+///
+///```rust
+///use serde::Serialize;
+///
+///#[derive(Serialize)]
+///struct Search {
+///    q: &'static str,
+///    page: Option<u32>,
+///    tags: Vec<&'static str>,
+///    filter: Filter,
+///}
+///
+///#[derive(Serialize)]
+///struct Filter {
+///    after: u16,
+///}
+///
+///let search = Search {
+///    q: "cat pics",
+///    page: None,
+///    tags: vec!["cute", "small"],
+///    filter: Filter { after: 2020 },
+///};
+///
+///assert_eq!(
+///    mavourings::query::to_string(&search).unwrap(),
+///    "q=cat%20pics&tags=cute&tags=small&filter[after]=2020"
+///);
+///```
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, QuerySerdeError> {
+	let mut pairs = vec![];
+	value.serialize(PairSerializer {
+		key: None,
+		pairs: &mut pairs,
+	})?;
+
+	let encoded: Vec<String> = pairs
+		.into_iter()
+		.map(|(key, value)| format!("{key}={}", Query::url_encode(value)))
+		.collect();
+
+	Ok(encoded.join("&"))
+}
+
+fn error<S: Into<String>>(message: S) -> QuerySerdeError {
+	QuerySerdeError {
+		field: String::new(),
+		message: message.into(),
+	}
+}
+
+/// Serializes one value into `key=value` pairs. The key is already encoded.
+/// `None` is the top level, which has to be a struct or map.
+struct PairSerializer<'a> {
+	key: Option<String>,
+	pairs: &'a mut Vec<(String, String)>,
+}
+
+impl<'a> PairSerializer<'a> {
+	fn push<S: ToString>(self, value: S) -> Result<(), QuerySerdeError> {
+		match self.key {
+			Some(key) => {
+				self.pairs.push((key, value.to_string()));
+				Ok(())
+			}
+			None => Err(error("only structs and maps can be serialized to a query")),
+		}
+	}
+
+	fn nested(&mut self, name: &str) -> PairSerializer<'_> {
+		let name = encode_key(name);
+
+		PairSerializer {
+			key: Some(match &self.key {
+				Some(key) => format!("{key}[{name}]"),
+				None => name,
+			}),
+			pairs: self.pairs,
+		}
+	}
+
+	fn repeated(&mut self) -> Result<PairSerializer<'_>, QuerySerdeError> {
+		match &self.key {
+			Some(key) => Ok(PairSerializer {
+				key: Some(key.clone()),
+				pairs: self.pairs,
+			}),
+			None => Err(error("only structs and maps can be serialized to a query")),
+		}
+	}
+}
+
+/// Encode a key but leave it readable; only what would break the query goes
+fn encode_key(key: &str) -> String {
+	let mut encoded = String::with_capacity(key.len());
+	for c in key.chars() {
+		match c {
+			'&' | '=' | '[' | ']' | '%' | '+' | '#' => {
+				encoded.push_str(&format!("%{:02x}", c as u32))
+			}
+			c if c.is_ascii_graphic() => encoded.push(c),
+			c => encoded.push_str(&Query::url_encode(c.to_string())),
+		}
+	}
+	encoded
+}
+
+macro_rules! serialize_display {
+	($($method:ident: $ty:ty,)*) => {
+		$(
+			fn $method(self, v: $ty) -> Result<Self::Ok, Self::Error> {
+				self.push(v)
+			}
+		)*
+	};
+}
+
+impl<'a> ser::Serializer for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	type SerializeSeq = Self;
+	type SerializeTuple = Self;
+	type SerializeTupleStruct = Self;
+	type SerializeTupleVariant = Self;
+	type SerializeMap = MapSerializer<'a>;
+	type SerializeStruct = Self;
+	type SerializeStructVariant = Self;
+
+	serialize_display! {
+		serialize_bool: bool,
+		serialize_i8: i8,
+		serialize_i16: i16,
+		serialize_i32: i32,
+		serialize_i64: i64,
+		serialize_i128: i128,
+		serialize_u8: u8,
+		serialize_u16: u16,
+		serialize_u32: u32,
+		serialize_u64: u64,
+		serialize_u128: u128,
+		serialize_f32: f32,
+		serialize_f64: f64,
+		serialize_char: char,
+		serialize_str: &str,
+	}
+
+	fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+		match std::str::from_utf8(v) {
+			Ok(v) => self.push(v),
+			Err(_) => Err(error("bytes must be UTF-8")),
+		}
+	}
+
+	fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+
+	fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+
+	fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+
+	fn serialize_unit_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+	) -> Result<Self::Ok, Self::Error> {
+		self.push(variant)
+	}
+
+	fn serialize_newtype_struct<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		value: &T,
+	) -> Result<Self::Ok, Self::Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_newtype_variant<T: Serialize + ?Sized>(
+		mut self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+		value: &T,
+	) -> Result<Self::Ok, Self::Error> {
+		value.serialize(self.nested(variant))
+	}
+
+	fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+		match self.key {
+			Some(_) => Ok(self),
+			None => Err(error("only structs and maps can be serialized to a query")),
+		}
+	}
+
+	fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+		self.serialize_seq(Some(len))
+	}
+
+	fn serialize_tuple_struct(
+		self,
+		_name: &'static str,
+		len: usize,
+	) -> Result<Self::SerializeTupleStruct, Self::Error> {
+		self.serialize_seq(Some(len))
+	}
+
+	fn serialize_tuple_variant(
+		mut self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeTupleVariant, Self::Error> {
+		let key = self.nested(variant).key;
+		Ok(PairSerializer {
+			key,
+			pairs: self.pairs,
+		})
+	}
+
+	fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+		Ok(MapSerializer {
+			parent: self,
+			key: None,
+		})
+	}
+
+	fn serialize_struct(
+		self,
+		_name: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeStruct, Self::Error> {
+		Ok(self)
+	}
+
+	fn serialize_struct_variant(
+		mut self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeStructVariant, Self::Error> {
+		let key = self.nested(variant).key;
+		Ok(PairSerializer {
+			key,
+			pairs: self.pairs,
+		})
+	}
+}
+
+impl<'a> ser::SerializeSeq for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+		value.serialize(self.repeated()?)
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a> ser::SerializeTuple for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+		ser::SerializeSeq::serialize_element(self, value)
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a> ser::SerializeTupleStruct for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+		ser::SerializeSeq::serialize_element(self, value)
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a> ser::SerializeTupleVariant for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+		ser::SerializeSeq::serialize_element(self, value)
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a> ser::SerializeStruct for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		key: &'static str,
+		value: &T,
+	) -> Result<(), Self::Error> {
+		value.serialize(self.nested(key)).map_err(|e| e.at(key))
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a> ser::SerializeStructVariant for PairSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		key: &'static str,
+		value: &T,
+	) -> Result<(), Self::Error> {
+		ser::SerializeStruct::serialize_field(self, key, value)
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+/// Map keys have to be turned into strings before the value can be nested
+/// under them
+pub struct MapSerializer<'a> {
+	parent: PairSerializer<'a>,
+	key: Option<String>,
+}
+
+impl<'a> ser::SerializeMap for MapSerializer<'a> {
+	type Ok = ();
+	type Error = QuerySerdeError;
+
+	fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+		self.key = Some(key.serialize(KeySerializer)?);
+		Ok(())
+	}
+
+	fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+		let key = self
+			.key
+			.take()
+			.ok_or_else(|| error("a value was given before its key"))?;
+
+		value
+			.serialize(self.parent.nested(&key))
+			.map_err(|e| e.at(&key))
+	}
+
+	fn end(self) -> Result<Self::Ok, Self::Error> {
+		Ok(())
+	}
+}
+
+/// Turns map keys into strings. Only things that look like a single value
+/// can be keys.
+struct KeySerializer;
+
+macro_rules! key_display {
+	($($method:ident: $ty:ty,)*) => {
+		$(
+			fn $method(self, v: $ty) -> Result<Self::Ok, Self::Error> {
+				Ok(v.to_string())
+			}
+		)*
+	};
+}
+
+impl ser::Serializer for KeySerializer {
+	type Ok = String;
+	type Error = QuerySerdeError;
+
+	type SerializeSeq = Impossible<String, QuerySerdeError>;
+	type SerializeTuple = Impossible<String, QuerySerdeError>;
+	type SerializeTupleStruct = Impossible<String, QuerySerdeError>;
+	type SerializeTupleVariant = Impossible<String, QuerySerdeError>;
+	type SerializeMap = Impossible<String, QuerySerdeError>;
+	type SerializeStruct = Impossible<String, QuerySerdeError>;
+	type SerializeStructVariant = Impossible<String, QuerySerdeError>;
+
+	key_display! {
+		serialize_bool: bool,
+		serialize_i8: i8,
+		serialize_i16: i16,
+		serialize_i32: i32,
+		serialize_i64: i64,
+		serialize_i128: i128,
+		serialize_u8: u8,
+		serialize_u16: u16,
+		serialize_u32: u32,
+		serialize_u64: u64,
+		serialize_u128: u128,
+		serialize_f32: f32,
+		serialize_f64: f64,
+		serialize_char: char,
+		serialize_str: &str,
+	}
+
+	fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_unit_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		variant: &'static str,
+	) -> Result<Self::Ok, Self::Error> {
+		Ok(variant.to_owned())
+	}
+
+	fn serialize_newtype_struct<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		value: &T,
+	) -> Result<Self::Ok, Self::Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_newtype_variant<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		_variant: &'static str,
+		_value: &T,
+	) -> Result<Self::Ok, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_tuple_struct(
+		self,
+		_name: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeTupleStruct, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_tuple_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		_variant: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeTupleVariant, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_struct(
+		self,
+		_name: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeStruct, Self::Error> {
+		Err(key_error())
+	}
+
+	fn serialize_struct_variant(
+		self,
+		_name: &'static str,
+		_variant_index: u32,
+		_variant: &'static str,
+		_len: usize,
+	) -> Result<Self::SerializeStructVariant, Self::Error> {
+		Err(key_error())
+	}
+}
+
+fn key_error() -> QuerySerdeError {
+	error("map keys must be strings, numbers, or unit variants")
+}
+
+impl ser::Error for QuerySerdeError {
+	fn custom<T: std::fmt::Display>(msg: T) -> Self {
+		error(msg.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use serde::{Deserialize, Serialize};
+
+	use super::to_string;
+	use crate::query::from_str;
+
+	#[derive(Debug, Serialize, Deserialize, PartialEq)]
+	enum Shape {
+		Square,
+		Circle(u32),
+	}
+
+	#[derive(Debug, Serialize, Deserialize, PartialEq)]
+	struct Form {
+		name: String,
+		subscribe: bool,
+		shape: Shape,
+		outline: Option<Shape>,
+		ids: Vec<u8>,
+		extra: BTreeMap<String, String>,
+	}
+
+	#[test]
+	fn round_trip() {
+		let form = Form {
+			name: String::from("gen & co=op"),
+			subscribe: false,
+			shape: Shape::Circle(4),
+			outline: None,
+			ids: vec![1, 2],
+			extra: BTreeMap::from([(String::from("k y"), String::from("🥺"))]),
+		};
+
+		let query = to_string(&form).unwrap();
+		assert_eq!(
+			query,
+			"name=gen%20%26%20co%3dop&subscribe=false&shape[Circle]=4&ids=1&ids=2&extra[k%20y]=%f0%9f%a5%ba"
+		);
+		assert_eq!(from_str::<Form>(&query).unwrap(), form);
+	}
+
+	#[test]
+	fn only_structs_and_maps() {
+		assert!(to_string(&5).is_err());
+		assert!(to_string(&vec![1, 2]).is_err());
+		assert_eq!(to_string(&BTreeMap::from([(1, "one")])).unwrap(), "1=one");
+	}
+}
@@ -0,0 +1,296 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{
+	cookie::{CookieJar, Encoding, PrivateCookies, SetCookie, SetCookieError, SignedCookies},
+	id::{random_base58, UserId},
+};
+
+/// Length of the random ID that lets us revoke a session
+const SESSION_ID_LENGTH: usize = 16;
+
+/// A session that lives entirely in a cookie. It's signed or encrypted by
+/// [CookieSessions] so the client can't forge one.
+///
+/// Keep `data` small, the whole thing has to fit in a cookie. After changing
+/// it, send a new cookie with [CookieSessions::login_cookie].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CookieSession {
+	/// Random, used to revoke this session when logging out
+	pub id: String,
+	pub uid: UserId,
+	/// Seconds since the Unix epoch
+	pub issued_at: u64,
+	/// Seconds since the Unix epoch
+	pub expires_at: u64,
+	pub data: BTreeMap<String, String>,
+}
+
+impl CookieSession {
+	pub fn get<S: AsRef<str>>(&self, key: S) -> Option<&str> {
+		self.data.get(key.as_ref()).map(String::as_str)
+	}
+
+	pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+		self.data.insert(key.into(), value.into());
+	}
+}
+
+enum Protection {
+	Signed(SignedCookies),
+	Private(PrivateCookies),
+}
+
+/// Issues and reads [CookieSession]s, for apps too small to want sessions
+/// saved with their `Users`.
+///
+/// Because the server doesn't keep the sessions, logging out can't delete
+/// one. Instead its ID goes on a revocation list, held in memory until the
+/// session would have expired anyway.
+pub struct CookieSessions {
+	protection: Protection,
+	name: String,
+	lifetime: Duration,
+	revoked: RwLock<HashMap<String, u64>>,
+}
+
+impl CookieSessions {
+	/// Sessions the client can read but not change
+	pub fn signed(signer: SignedCookies) -> Self {
+		Self::new(Protection::Signed(signer))
+	}
+
+	/// Sessions the client can neither read nor change
+	pub fn private(crypter: PrivateCookies) -> Self {
+		Self::new(Protection::Private(crypter))
+	}
+
+	fn new(protection: Protection) -> Self {
+		Self {
+			protection,
+			name: String::from("session"),
+			lifetime: Duration::from_secs(60 * 60 * 24 * 30),
+			revoked: RwLock::new(HashMap::new()),
+		}
+	}
+
+	/// The name of the cookie. Defaults to `session`
+	pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+		self.name = name.into();
+		self
+	}
+
+	/// How long a session lasts. Defaults to 30 days, like a `users::Session`
+	pub fn lifetime(mut self, lifetime: Duration) -> Self {
+		self.lifetime = lifetime;
+		self
+	}
+
+	/// Start a session for a user. Send it with [CookieSessions::login_cookie]
+	pub fn new_session(&self, uid: UserId) -> CookieSession {
+		let now = now();
+
+		CookieSession {
+			id: random_base58(SESSION_ID_LENGTH),
+			uid,
+			issued_at: now,
+			expires_at: now + self.lifetime.as_secs(),
+			data: BTreeMap::new(),
+		}
+	}
+
+	/// Get the value bit of a Set-Cookie header to store the session. Fails
+	/// with [SetCookieError::TooLarge] if `data` has made it too big for a
+	/// browser to keep.
+	pub fn login_cookie(&self, session: &CookieSession) -> Result<String, SetCookieError> {
+		let json = serde_json::to_string(session).expect("a session always serializes");
+		let remaining = session.expires_at.saturating_sub(now());
+
+		let cookie = SetCookie::new(self.name.clone(), json)
+			.secure(true)
+			.httponly(true)
+			.max_age(Some(Duration::from_secs(remaining)))
+			.path(Some(String::from("/")));
+
+		match &self.protection {
+			// JSON isn't cookie-safe, so it's base64 encoded before it's signed
+			Protection::Signed(signer) => signer.sign(cookie.encoding(Encoding::Base64)),
+			// Encrypting gives base64 already
+			Protection::Private(crypter) => crypter.encrypt(cookie),
+		}
+		.try_as_string()
+	}
+
+	/// Revoke the session and get the value bit of a Set-Cookie header to
+	/// clear it from the client
+	pub async fn logout(&self, session: &CookieSession) -> String {
+		let now = now();
+
+		{
+			let mut revoked = self.revoked.write().await;
+			revoked.retain(|_, expires_at| *expires_at > now);
+			revoked.insert(session.id.clone(), session.expires_at);
+		}
+
+		SetCookie::removal(self.name.clone())
+			.path(Some(String::from("/")))
+			.as_string()
+	}
+
+	/// Find a session in the request's cookies. It has to be signed or
+	/// encrypted by us, not expired, and not revoked.
+	pub async fn session_from_jar(&self, jar: &CookieJar) -> Option<CookieSession> {
+		let now = now();
+
+		for value in jar.get_all(&self.name) {
+			let json = match &self.protection {
+				Protection::Signed(signer) => signer
+					.verify(&self.name, value)
+					.and_then(|encoded| Encoding::Base64.decode(&self.name, encoded).ok()),
+				Protection::Private(crypter) => crypter.decrypt(&self.name, value),
+			};
+
+			let session = json.and_then(|json| serde_json::from_str::<CookieSession>(&json).ok());
+
+			match session {
+				Some(session) if session.expires_at > now => {
+					// There might be another cookie of the same name, set
+					// for a more specific path, that's still good
+					if self.revoked.read().await.contains_key(&session.id) {
+						continue;
+					}
+
+					return Some(session);
+				}
+				_ => continue,
+			}
+		}
+
+		None
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::CookieSessions;
+	use crate::{
+		cookie::{parse_header, PrivateCookies, SetCookieError, SignedCookies, MAX_COOKIE_SIZE},
+		id::UserId,
+	};
+
+	const KEY: [u8; 32] = *b"0123456789abcdef0123456789abcdef";
+
+	/// Turn a Set-Cookie value into the Cookie header a browser would send back
+	fn send_back(set_cookie: &str) -> String {
+		set_cookie.split(';').next().unwrap().to_owned()
+	}
+
+	#[tokio::test]
+	async fn round_trip() {
+		for sessions in [
+			CookieSessions::signed(SignedCookies::new(KEY)),
+			CookieSessions::private(PrivateCookies::new(KEY)),
+		] {
+			let mut session = sessions.new_session(UserId::from(String::from("abc123")));
+			session.set("theme", "dark");
+
+			let cookie = send_back(&sessions.login_cookie(&session).unwrap());
+			assert!(cookie.starts_with("session="));
+
+			let read = sessions.session_from_jar(&parse_header(&cookie)).await;
+			assert_eq!(read, Some(session));
+		}
+	}
+
+	#[test]
+	fn private_encrypts_the_json() {
+		let sessions = CookieSessions::private(PrivateCookies::new(KEY));
+		let session = sessions.new_session(UserId::from(String::from("abc123")));
+		let cookie = send_back(&sessions.login_cookie(&session).unwrap());
+
+		let json = PrivateCookies::new(KEY)
+			.decrypt("session", &cookie["session=".len()..])
+			.unwrap();
+		assert_eq!(json, serde_json::to_string(&session).unwrap());
+	}
+
+	#[test]
+	fn rejects_oversized_data() {
+		for sessions in [
+			CookieSessions::signed(SignedCookies::new(KEY)),
+			CookieSessions::private(PrivateCookies::new(KEY)),
+		] {
+			let mut session = sessions.new_session(UserId::from(String::from("abc123")));
+			session.set("notes", "a".repeat(MAX_COOKIE_SIZE));
+
+			assert!(matches!(
+				sessions.login_cookie(&session),
+				Err(SetCookieError::TooLarge(_))
+			));
+		}
+	}
+
+	#[tokio::test]
+	async fn rejects_forged_and_expired() {
+		let sessions = CookieSessions::signed(SignedCookies::new(KEY));
+		let session = sessions.new_session(UserId::from(String::from("abc123")));
+		let cookie = send_back(&sessions.login_cookie(&session).unwrap());
+
+		let other_key = CookieSessions::signed(SignedCookies::new(*b"another key"));
+		assert_eq!(
+			other_key.session_from_jar(&parse_header(&cookie)).await,
+			None
+		);
+
+		let expired = CookieSessions::private(PrivateCookies::new(KEY)).lifetime(Duration::ZERO);
+		let session = expired.new_session(UserId::from(String::from("abc123")));
+		let cookie = send_back(&expired.login_cookie(&session).unwrap());
+		assert_eq!(expired.session_from_jar(&parse_header(&cookie)).await, None);
+	}
+
+	#[tokio::test]
+	async fn logout_revokes() {
+		let sessions = CookieSessions::private(PrivateCookies::new(KEY)).name("sess");
+		let session = sessions.new_session(UserId::from(String::from("abc123")));
+		let jar = parse_header(&send_back(&sessions.login_cookie(&session).unwrap()));
+
+		assert!(sessions.session_from_jar(&jar).await.is_some());
+
+		let clear = sessions.logout(&session).await;
+		assert!(clear.starts_with("sess=;"));
+		assert!(clear.contains("Max-Age=0"));
+		assert_eq!(sessions.session_from_jar(&jar).await, None);
+	}
+
+	#[tokio::test]
+	async fn skips_revoked_for_the_next_cookie() {
+		let sessions = CookieSessions::signed(SignedCookies::new(KEY));
+		let old = sessions.new_session(UserId::from(String::from("abc123")));
+		let new = sessions.new_session(UserId::from(String::from("abc123")));
+		sessions.logout(&old).await;
+
+		let header = format!(
+			"{}; {}",
+			send_back(&sessions.login_cookie(&old).unwrap()),
+			send_back(&sessions.login_cookie(&new).unwrap())
+		);
+		assert_eq!(
+			sessions.session_from_jar(&parse_header(&header)).await,
+			Some(new)
+		);
+	}
+}
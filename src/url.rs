@@ -0,0 +1,361 @@
+use std::{
+	fmt,
+	path::{Component, Path, PathBuf},
+	str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::query::{DecodeMode, Query, QueryParseError};
+
+/// The target of an HTTP request split into its path, query, and fragment.
+/// Nothing is decoded until you ask for it.
+///
+/// Both the usual origin form, `/path?query`, and the absolute form,
+/// `http://example.com/path?query`, are accepted. For the absolute form only
+/// the path onwards is kept.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::url::RequestTarget;
+///
+///let target: RequestTarget = "/posts/./2024/../hello%20world?page=2#top".parse().unwrap();
+///
+///assert_eq!(target.path(), "/posts/./2024/../hello%20world");
+///assert_eq!(target.query().unwrap().get_first_value("page"), Some("2"));
+///assert_eq!(target.fragment(), Some("top"));
+///
+///let target = target.normalize();
+///assert_eq!(target.path(), "/posts/hello%20world");
+///assert_eq!(target.decoded_segments().unwrap(), vec!["posts", "hello world"]);
+///```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestTarget {
+	path: String,
+	query: Option<String>,
+	fragment: Option<String>,
+}
+
+impl RequestTarget {
+	/// The path, still percent-encoded
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// The query string without the `?`, still percent-encoded
+	pub fn query_str(&self) -> Option<&str> {
+		self.query.as_deref()
+	}
+
+	/// The parsed query string. Without one it's an empty [Query].
+	pub fn query(&self) -> Result<Query, QueryParseError> {
+		self.query.as_deref().unwrap_or_default().parse()
+	}
+
+	/// The fragment without the `#`. Browsers don't send these, but links have
+	/// them.
+	pub fn fragment(&self) -> Option<&str> {
+		self.fragment.as_deref()
+	}
+
+	/// The path's segments, still percent-encoded. See [segments]
+	pub fn segments(&self) -> impl Iterator<Item = &str> {
+		segments(&self.path)
+	}
+
+	/// The path's segments, percent-decoded. A `+` is left alone, it only
+	/// means a space in a query.
+	pub fn decoded_segments(&self) -> Result<Vec<String>, QueryParseError> {
+		self.segments()
+			.map(|segment| Query::url_decode(segment, false))
+			.collect()
+	}
+
+	/// The same target with its path normalized, see [normalize_path]
+	pub fn normalize(mut self) -> Self {
+		self.path = normalize_path(&self.path);
+		self
+	}
+
+	/// Find the file this path points to under `root`, see [join]
+	pub fn join_to<P: AsRef<Path>>(&self, root: P) -> Result<PathBuf, UrlError> {
+		join(root, &self.path)
+	}
+}
+
+impl FromStr for RequestTarget {
+	type Err = UrlError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (rest, fragment) = match s.split_once('#') {
+			Some((rest, fragment)) => (rest, Some(fragment.to_owned())),
+			None => (s, None),
+		};
+
+		let (path, query) = match rest.split_once('?') {
+			Some((path, query)) => (path, Some(query.to_owned())),
+			None => (rest, None),
+		};
+
+		let path = if path.starts_with('/') {
+			path
+		} else {
+			absolute_form_path(path).ok_or_else(|| UrlError::InvalidTarget(s.to_owned()))?
+		};
+
+		if path.bytes().any(|b| b.is_ascii_control() || b == b' ') {
+			return Err(UrlError::InvalidTarget(s.to_owned()));
+		}
+
+		Ok(Self {
+			path: path.to_owned(),
+			query,
+			fragment,
+		})
+	}
+}
+
+impl fmt::Display for RequestTarget {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.path)?;
+
+		if let Some(query) = &self.query {
+			write!(f, "?{query}")?;
+		}
+
+		if let Some(fragment) = &self.fragment {
+			write!(f, "#{fragment}")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// The path of an absolute-form target, `scheme://authority/path`. A target
+/// with no path after the authority has the path `/`.
+fn absolute_form_path(target: &str) -> Option<&str> {
+	let (scheme, rest) = target.split_once("://")?;
+
+	let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+		&& scheme
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+
+	if !valid_scheme {
+		return None;
+	}
+
+	Some(rest.find('/').map(|slash| &rest[slash..]).unwrap_or("/"))
+}
+
+/// The segments of a path, split on `/`. Empty segments, like from `//` or a
+/// trailing `/`, are skipped.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::url::segments;
+///
+///assert_eq!(segments("/a//b/c/").collect::<Vec<_>>(), vec!["a", "b", "c"]);
+///```
+pub fn segments(path: &str) -> impl Iterator<Item = &str> {
+	path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Remove `.` and `..` segments from a path, as in RFC 3986 section 5.2.4.
+/// A `..` can't climb above the start of the path. Percent-encoded dots, like
+/// `%2e%2E`, count as dots, as they do in browsers.
+///
+/// # Examples
+///
+///```rust
+///use mavourings::url::normalize_path;
+///
+///assert_eq!(normalize_path("/a/b/c/./../../g"), "/a/g");
+///assert_eq!(normalize_path("/static/%2e%2e/%2E%2e/etc/passwd"), "/etc/passwd");
+///assert_eq!(normalize_path("/docs/.."), "/");
+///```
+pub fn normalize_path(path: &str) -> String {
+	let (absolute, rest) = match path.strip_prefix('/') {
+		Some(rest) => (true, rest),
+		None => (false, path),
+	};
+
+	let mut output: Vec<&str> = vec![];
+	// A path ending in a dot segment is a directory, so it keeps its slash
+	let mut trailing_slash = false;
+
+	for segment in rest.split('/') {
+		trailing_slash = true;
+
+		match dots(segment) {
+			Some(Dots::One) => (),
+			Some(Dots::Two) => {
+				output.pop();
+			}
+			None => {
+				output.push(segment);
+				trailing_slash = false;
+			}
+		}
+	}
+
+	let mut normalized = String::with_capacity(path.len());
+	if absolute {
+		normalized.push('/');
+	}
+	normalized.push_str(&output.join("/"));
+	if trailing_slash && !output.is_empty() {
+		normalized.push('/');
+	}
+
+	normalized
+}
+
+enum Dots {
+	One,
+	Two,
+}
+
+fn dots(segment: &str) -> Option<Dots> {
+	match segment.to_ascii_lowercase().as_str() {
+		"." | "%2e" => Some(Dots::One),
+		".." | ".%2e" | "%2e." | "%2e%2e" => Some(Dots::Two),
+		_ => None,
+	}
+}
+
+/// Join a request path onto a directory, for finding the file to serve. Each
+/// segment is percent-decoded and has to be a plain name: anything that
+/// could step outside `root`, like `..`, an encoded `/` or `\`, or a drive
+/// letter, is an error. `.` segments are skipped.
+///
+/// # Examples
+///
+///```rust
+///use std::path::PathBuf;
+///use mavourings::url::{join, UrlError};
+///
+///assert_eq!(join("public", "/css/site%20main.css").unwrap(), PathBuf::from("public/css/site main.css"));
+///assert!(matches!(join("public", "/../secret"), Err(UrlError::UnsafeSegment(_))));
+///assert!(matches!(join("public", "/a%2F..%2F..%2Fsecret"), Err(UrlError::UnsafeSegment(_))));
+///```
+///
+/// Use it with [file_reply](crate::file_reply) to serve a directory:
+///
+///```rust,no_run
+///# async fn serve(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+///let response = mavourings::file_reply(mavourings::url::join("public", path)?).await?;
+///# Ok(())
+///# }
+///```
+pub fn join<P: AsRef<Path>>(root: P, path: &str) -> Result<PathBuf, UrlError> {
+	let mut joined = root.as_ref().to_path_buf();
+
+	for segment in segments(path) {
+		let decoded = Query::url_decode_with(segment, false, DecodeMode::Strict)?;
+
+		if decoded.contains(['/', '\\', '\0']) {
+			return Err(UrlError::UnsafeSegment(decoded));
+		}
+
+		let mut components = Path::new(&decoded).components();
+		match (components.next(), components.next()) {
+			(Some(Component::CurDir), None) => continue,
+			(Some(Component::Normal(name)), None) if name == decoded.as_str() => joined.push(name),
+			_ => return Err(UrlError::UnsafeSegment(decoded)),
+		}
+	}
+
+	Ok(joined)
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum UrlError {
+	#[error("{0:?} is not a request target")]
+	InvalidTarget(String),
+	#[error("the path segment {0:?} could leave the directory")]
+	UnsafeSegment(String),
+	#[error("the path could not be decoded: {0}")]
+	Decode(#[from] QueryParseError),
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use super::{join, normalize_path, RequestTarget, UrlError};
+
+	#[test]
+	fn parse() {
+		let target: RequestTarget = "http://example.com?q=1".parse().unwrap();
+		assert_eq!(target.path(), "/");
+		assert_eq!(target.query_str(), Some("q=1"));
+		assert_eq!(target.fragment(), None);
+
+		let target: RequestTarget = "/a/b?#".parse().unwrap();
+		assert_eq!(target.to_string(), "/a/b?#");
+		assert_eq!(target.segments().collect::<Vec<_>>(), vec!["a", "b"]);
+
+		for invalid in ["", "*", "a/b", "1http://x/", "/a b"] {
+			assert_eq!(
+				invalid.parse::<RequestTarget>(),
+				Err(UrlError::InvalidTarget(invalid.to_owned()))
+			);
+		}
+	}
+
+	/// From RFC 3986 section 5.4, with the base's path merged in
+	#[test]
+	fn remove_dot_segments() {
+		let vectors = [
+			("/b/c/g", "/b/c/g"),
+			("/b/c/./g", "/b/c/g"),
+			("/b/c/g/", "/b/c/g/"),
+			("/b/c/.", "/b/c/"),
+			("/b/c/./", "/b/c/"),
+			("/b/c/..", "/b/"),
+			("/b/c/../", "/b/"),
+			("/b/c/../g", "/b/g"),
+			("/b/c/../..", "/"),
+			("/b/c/../../g", "/g"),
+			("/b/c/../../../g", "/g"),
+			("/b/c/../../../../g", "/g"),
+			("/./g", "/g"),
+			("/../g", "/g"),
+			("/b/c/g.", "/b/c/g."),
+			("/b/c/.g", "/b/c/.g"),
+			("/b/c/g..", "/b/c/g.."),
+			("/b/c/..g", "/b/c/..g"),
+			("/b/c/./../g", "/b/g"),
+			("/b/c/./g/.", "/b/c/g/"),
+			("/b/c/g/./h", "/b/c/g/h"),
+			("/b/c/g/../h", "/b/c/h"),
+			("mid/content=5/../6", "mid/6"),
+			("/", "/"),
+			("", ""),
+		];
+
+		for (input, output) in vectors {
+			assert_eq!(normalize_path(input), output, "{input}");
+		}
+	}
+
+	#[test]
+	fn safe_join() {
+		assert_eq!(
+			join("/srv", "/./a/./b.txt").unwrap(),
+			PathBuf::from("/srv/a/b.txt")
+		);
+
+		for unsafe_path in ["/..", "/a/%2e%2e/b", "/a%5Cb", "/a%00"] {
+			assert!(
+				matches!(join("/srv", unsafe_path), Err(UrlError::UnsafeSegment(_))),
+				"{unsafe_path}"
+			);
+		}
+
+		assert!(matches!(join("/srv", "/a%zz"), Err(UrlError::Decode(_))));
+	}
+}
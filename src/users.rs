@@ -1,23 +1,16 @@
 use std::{collections::HashMap, fmt, io, path::Path, str::FromStr, time::Duration};
 
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use rand::{rngs::OsRng, Rng};
+use rand::rngs::OsRng;
 use tokio::{io::AsyncWriteExt, sync::RwLock};
 
-const BASE58: &'static [u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+use crate::query::Query;
+
+pub use crate::id::{random_base58, UserId};
+
 const USER_ID_LENGTH: usize = 6;
 const SESSION_ID_LENGTH: usize = 12;
 
-/// Random Base58 string, `count` characters long, using OsRng which is assumed
-/// to be secure
-/// > assumed that system always provides high-quality cryptographically secure random data
-pub fn random_base58(count: usize) -> String {
-	let mut rng = OsRng::default();
-	std::iter::from_fn(|| Some(BASE58[rng.gen_range(0..BASE58.len())] as char))
-		.take(count)
-		.collect()
-}
-
 #[derive(Debug)]
 pub struct Users {
 	pub(crate) users: RwLock<HashMap<UserId, UserEntry>>,
@@ -156,6 +149,90 @@ impl Users {
 		None
 	}
 
+	/// Registers a User that signs in with an identity provider instead of a
+	/// password. They're linked to the issuer and subject of the external
+	/// account and can only log in with [Users::login_external].
+	pub async fn register_external(
+		&self,
+		email: Option<String>,
+		username: String,
+		external: ExternalId,
+	) -> Result<Session, Error> {
+		if self.stub_by_username(&username).await.is_some() {
+			return Err(Error::UsernameTaken);
+		}
+
+		if self.stub_by_external(&external).await.is_some() {
+			return Err(Error::ExternalIdTaken);
+		}
+
+		let mut entry = UserEntry {
+			id: UserEntry::generate_user_id(),
+			email,
+			username,
+			password_hash: String::new(),
+			sessions: vec![],
+			external: vec![external],
+		};
+
+		let mut users = self.users.write().await;
+		loop {
+			if users.contains_key(&entry.id) {
+				entry.id = UserEntry::generate_user_id();
+			} else {
+				let session = entry.new_session();
+				users.insert(entry.id.clone(), entry);
+				break Ok(session);
+			}
+		}
+	}
+
+	/// Link an account at an identity provider to an existing user so they can
+	/// use it with [Users::login_external]. A user can have more than one, but
+	/// each external account can only belong to one user.
+	pub async fn link_external(&self, uid: &UserId, external: ExternalId) -> Result<(), Error> {
+		let mut lock = self.users.write().await;
+
+		if let Some(owner) = lock
+			.values()
+			.find(|entry| entry.external.contains(&external))
+		{
+			return if owner.id == *uid {
+				Ok(())
+			} else {
+				Err(Error::ExternalIdTaken)
+			};
+		}
+
+		match lock.get_mut(uid) {
+			None => Err(Error::UnknownUser),
+			Some(entry) => {
+				entry.external.push(external);
+				Ok(())
+			}
+		}
+	}
+
+	/// Login the user linked to an external account. Returns a [Session] if
+	/// one was found.
+	pub async fn login_external(&self, external: &ExternalId) -> Option<Session> {
+		let mut lock = self.users.write().await;
+
+		lock.values_mut()
+			.find(|entry| entry.external.contains(external))
+			.map(|entry| entry.new_session())
+	}
+
+	/// Searches for the user linked to an external account, returning a
+	/// [UserStub] if one is found
+	pub async fn stub_by_external(&self, external: &ExternalId) -> Option<UserStub> {
+		let lock = self.users.read().await;
+
+		lock.values()
+			.find(|entry| entry.external.contains(external))
+			.map(|entry| entry.stub())
+	}
+
 	pub async fn stub_by_uid(&self, uid: UserId) -> Option<UserStub> {
 		self.users.read().await.get(&uid).map(|u| u.stub())
 	}
@@ -206,6 +283,10 @@ pub enum Error {
 	InvalidUsername,
 	#[error("Username already in use")]
 	UsernameTaken,
+	#[error("No user with that ID")]
+	UnknownUser,
+	#[error("That account is already linked to another user")]
+	ExternalIdTaken,
 }
 
 /// Information about a user. Returned by [UserEntry::register] and [UserEnry::login].
@@ -227,28 +308,24 @@ impl Session {
 	}
 
 	pub fn logout_cookie(&self) -> String {
-		session_clear_cookie(&self.sid)
+		session_removal_cookie()
 	}
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, serde::Serialize, serde::Deserialize)]
-pub struct UserId(String);
-
-impl UserId {
-	pub fn as_str(&self) -> &str {
-		&self.0
-	}
-}
-
-impl fmt::Display for UserId {
-	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		write!(f, "{}", self.0)
-	}
+/// An account at an identity provider, like an OpenID Connect issuer and the
+/// subject identifier it gave the user.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ExternalId {
+	pub issuer: String,
+	pub subject: String,
 }
 
-impl From<String> for UserId {
-	fn from(s: String) -> Self {
-		Self(s)
+impl ExternalId {
+	pub fn new<I: Into<String>, S: Into<String>>(issuer: I, subject: S) -> Self {
+		Self {
+			issuer: issuer.into(),
+			subject: subject.into(),
+		}
 	}
 }
 
@@ -257,8 +334,10 @@ pub struct UserEntry {
 	pub id: UserId,
 	pub email: Option<String>,
 	pub username: String,
+	/// Empty for users registered with [Users::register_external]
 	pub password_hash: String,
 	sessions: Vec<SessionId>,
+	external: Vec<ExternalId>,
 }
 
 impl UserEntry {
@@ -273,9 +352,15 @@ impl UserEntry {
 			username,
 			password_hash,
 			sessions: vec![],
+			external: vec![],
 		}
 	}
 
+	/// Accounts at identity providers linked to this user
+	pub fn external_ids(&self) -> &[ExternalId] {
+		&self.external
+	}
+
 	pub fn new_session(&mut self) -> Session {
 		let sid = Self::generate_session_id();
 		self.sessions.push(sid.clone());
@@ -304,7 +389,11 @@ impl UserEntry {
 	}
 
 	fn verify_password(&self, password: String) -> bool {
-		let parsed_hash = PasswordHash::new(&self.password_hash).unwrap();
+		// Users from an identity provider don't have a password to check
+		let parsed_hash = match PasswordHash::new(&self.password_hash) {
+			Ok(hash) => hash,
+			Err(_) => return false,
+		};
 
 		Argon2::default()
 			.verify_password(password.as_bytes(), &parsed_hash)
@@ -341,7 +430,21 @@ impl fmt::Display for UserEntry {
 			session_str.push(',');
 		}
 
-		write!(f, "sessions={}", session_str)
+		write!(f, "sessions={}", session_str)?;
+
+		if !self.external.is_empty() {
+			let mut external_str = String::new();
+			for external in &self.external {
+				external_str.push_str(&Query::url_encode(&external.issuer));
+				external_str.push(';');
+				external_str.push_str(&Query::url_encode(&external.subject));
+				external_str.push(',');
+			}
+
+			write!(f, " external={}", external_str)?;
+		}
+
+		Ok(())
 	}
 }
 
@@ -394,12 +497,32 @@ impl FromStr for UserEntry {
 				.collect(),
 		};
 
+		let external = match splits.next() {
+			None => vec![],
+			Some(external_str) => match external_str.strip_prefix("external=") {
+				None => return Err(()),
+				Some(externals) => externals
+					.split(',')
+					.filter(|external| !external.is_empty())
+					.map(|external| {
+						let (issuer, subject) = external.split_once(';').ok_or(())?;
+
+						Ok(ExternalId {
+							issuer: Query::url_decode(issuer, false).map_err(|_| ())?,
+							subject: Query::url_decode(subject, false).map_err(|_| ())?,
+						})
+					})
+					.collect::<Result<_, ()>>()?,
+			},
+		};
+
 		Ok(Self {
 			id,
 			email,
 			username,
 			password_hash,
 			sessions,
+			external,
 		})
 	}
 }
@@ -439,19 +562,24 @@ fn session_set_cookie(sid: &SessionId) -> String {
 		.as_string()
 }
 
-/// Get the value bit of a Set-Cookie header to clear a session
-pub fn session_clear_cookie(sid: &SessionId) -> String {
-	crate::cookie::SetCookie::new("sid".into(), sid.to_string())
-		.secure(true)
-		.httponly(true)
-		.max_age(Some(Duration::from_secs(0)))
+/// Get the value bit of a Set-Cookie header to clear a session. It doesn't
+/// need the session ID, there's no reason to send it again.
+pub fn session_removal_cookie() -> String {
+	crate::cookie::SetCookie::removal("sid")
 		.path(Some(String::from("/")))
 		.as_string()
 }
 
+/// Get the value bit of a Set-Cookie header to clear a session. The ID isn't
+/// used any more.
+#[deprecated(note = "the session ID isn't sent again; use session_removal_cookie")]
+pub fn session_clear_cookie(_sid: &SessionId) -> String {
+	session_removal_cookie()
+}
+
 #[cfg(test)]
 mod tests {
-	use super::UserEntry;
+	use super::{ExternalId, UserEntry};
 
 	fn check_entry_saveload(entry: UserEntry) {
 		let entry_string = entry.to_string();
@@ -473,5 +601,12 @@ mod tests {
 		entry_with_sessions.new_session();
 		entry_with_sessions.new_session();
 		check_entry_saveload(entry_with_sessions);
+
+		let mut entry_with_external = UserEntry::new_user(None, "gen".into(), "password".into());
+		entry_with_external
+			.external
+			.push(ExternalId::new("https://id.example.com", "a;weird,subject"));
+		entry_with_external.new_session();
+		check_entry_saveload(entry_with_external);
 	}
 }